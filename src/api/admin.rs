@@ -0,0 +1,71 @@
+use std::str::FromStr;
+
+use actix_web::{
+    get, put,
+    web::{Data, Json, Path, Query},
+};
+use actix_web_grants::protect;
+use serde::{Deserialize, Serialize};
+use surrealitos::SurrealId;
+
+use crate::{
+    api::{Page, PaginationParameters},
+    error::{Error, Result},
+    model::{
+        Controller,
+        role::{self, Role},
+        token::TokenController,
+        user::{User, UserController},
+    },
+    repo::surreal::SurrealDB,
+};
+
+#[derive(Serialize, Deserialize)]
+pub struct RolesPayload {
+    roles: Vec<Role>,
+}
+
+#[get("/users")]
+#[protect(role::USERS_READ)]
+pub async fn list_users(
+    db: Data<SurrealDB>,
+    pagination: Query<PaginationParameters>,
+) -> Result<Json<Page<User>>> {
+    let (users, total) = UserController::list(&db.surreal, &pagination).await?;
+    Ok(Json(Page::new(users, total, &pagination)))
+}
+
+#[get("/users/{id}")]
+#[protect(role::USERS_READ)]
+pub async fn get_user(db: Data<SurrealDB>, path: Path<String>) -> Result<Json<User>> {
+    let id = SurrealId::from_str(&path.into_inner())?;
+    let user = UserController::get(&db.surreal, &id)
+        .await?
+        .ok_or(Error::NotFound("user".to_string()))?;
+
+    Ok(Json(user))
+}
+
+#[put("/users/{id}/roles")]
+#[protect(role::USERS_WRITE)]
+pub async fn set_user_roles(
+    db: Data<SurrealDB>,
+    path: Path<String>,
+    body: Json<RolesPayload>,
+) -> Result<Json<User>> {
+    let id = SurrealId::from_str(&path.into_inner())?;
+    let roles = body.into_inner().roles;
+
+    if let Some(role) = roles.iter().find(|role| !role.is_grantable()) {
+        return Err(Error::BadRequest(format!(
+            "{role:?} comes from the account type and can't be granted"
+        )));
+    }
+
+    let user = UserController::set_roles(&db.surreal, &id, roles).await?;
+
+    // Permissions live in the tokens, signing the user out makes the change apply right away
+    TokenController::delete_by_user(&db.surreal, &user.id.to_string()).await?;
+
+    Ok(Json(user))
+}
@@ -0,0 +1,92 @@
+use std::str::FromStr;
+
+use actix_web::{
+    HttpMessage, HttpRequest, delete, get, post,
+    web::{Data, Json, Path},
+};
+use chrono::Duration;
+use serde::{Deserialize, Serialize};
+use surrealitos::SurrealId;
+
+use crate::{
+    error::{Error, Result},
+    model::{
+        Controller,
+        api_key::{ApiKey, ApiKeyController, CreatedApiKey},
+        token::Claims,
+        user::UserController,
+    },
+    repo::surreal::SurrealDB,
+};
+
+#[derive(Serialize, Deserialize)]
+pub struct ApiKeyPayload {
+    name: String,
+    scopes: Option<Vec<String>>, // Every permission of the current session when left out
+    #[serde(alias = "expiresInDays")]
+    expires_in_days: Option<i64>,
+}
+
+#[get("")]
+pub async fn get_api_keys(db: Data<SurrealDB>, req: HttpRequest) -> Result<Json<Vec<ApiKey>>> {
+    let claims = req.extensions().get::<Claims>().unwrap().clone();
+    let id = SurrealId::from_str(&claims.sub)?;
+
+    let keys = ApiKeyController::get_by_user(&db.surreal, &id.to_string()).await?;
+    Ok(Json(keys))
+}
+
+#[post("")]
+pub async fn create_api_key(
+    db: Data<SurrealDB>,
+    body: Json<ApiKeyPayload>,
+    req: HttpRequest,
+) -> Result<Json<CreatedApiKey>> {
+    let claims = req.extensions().get::<Claims>().unwrap().clone();
+    let id = SurrealId::from_str(&claims.sub)?;
+    let user = UserController::get(&db.surreal, &id)
+        .await?
+        .ok_or(Error::WrongCredentials)?;
+
+    let payload = body.into_inner();
+    let name = payload.name.trim();
+    if name.is_empty() {
+        return Err(Error::BadRequest("name is required".to_string()));
+    }
+
+    let ttl = match payload.expires_in_days {
+        Some(days) if days <= 0 => {
+            return Err(Error::BadRequest(
+                "expiresInDays has to be positive".to_string(),
+            ));
+        }
+        Some(days) => Some(Duration::days(days)),
+        None => None,
+    };
+
+    let scopes = payload.scopes.unwrap_or_else(|| claims.permissions.clone());
+    let api_key =
+        ApiKeyController::create(&db.surreal, &user, &claims.permissions, name, scopes, ttl)
+            .await?;
+
+    Ok(Json(api_key))
+}
+
+#[delete("/{id}")]
+pub async fn delete_api_key(
+    db: Data<SurrealDB>,
+    path: Path<String>,
+    req: HttpRequest,
+) -> Result<Json<String>> {
+    let claims = req.extensions().get::<Claims>().unwrap().clone();
+    let user_id = SurrealId::from_str(&claims.sub)?;
+    let id = SurrealId::from_str(&path.into_inner())
+        .map_err(|_| Error::NotFound("api_key".to_string()))?;
+
+    let deleted = ApiKeyController::delete(&db.surreal, &user_id.to_string(), &id).await?;
+    if !deleted {
+        return Err(Error::NotFound("api_key".to_string()));
+    }
+
+    Ok(Json(String::from("Success!")))
+}
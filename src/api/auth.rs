@@ -1,19 +1,27 @@
-use std::str::FromStr;
+use std::{collections::BTreeMap, str::FromStr};
 
 use actix_web::{
-    HttpMessage, HttpRequest, get, post,
+    HttpMessage, HttpRequest, delete, get, post,
     web::{Data, Json, Path},
 };
+use actix_web_httpauth::extractors::bearer::BearerAuth;
+use chrono::Duration;
 use serde::{Deserialize, Serialize};
 use surrealitos::SurrealId;
 
 use crate::{
+    api::{client_ip, two_factor::throttled_two_factor},
+    config,
+    connector::mailer::{Mail, Mailer},
     error::{Error, Result},
     model::{
         Controller,
         device::{DeviceController, DevicePatch, NewDevice},
+        one_time_token::{OneTimeTokenController, OneTimeTokenKind},
+        throttle::{self, Policy, ThrottleController},
         token::{Claims, TokenController, TokenManager, TokenResponse},
-        user::{NewUser, PasswordHasher, UserController},
+        two_factor::TwoFactorController,
+        user::{NewUser, PasswordHasher, User, UserController, UserPatch, normalize_email},
     },
     repo::surreal::SurrealDB,
 };
@@ -36,6 +44,28 @@ pub struct LoginPayload {
     pub device: NewDevice,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct TwoFactorLoginPayload {
+    pub challenge: String,
+    pub code: String,
+    pub device: NewDevice,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct TwoFactorChallenge {
+    pub two_factor_required: bool,
+    pub challenge: String,
+}
+
+// Accounts with 2FA get a challenge instead of tokens
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum LoginResponse {
+    Tokens(TokenResponse),
+    Challenge(TwoFactorChallenge),
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct RefreshPayload {
     #[serde(alias = "refreshToken")]
@@ -49,16 +79,77 @@ pub struct CheckEmailResponse {
     exists: bool,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct VerifyEmailPayload {
+    token: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UpgradePayload {
+    email: String,
+    password: String,
+    name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ForgotPasswordPayload {
+    email: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ResetPasswordPayload {
+    token: String,
+    password: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct Session {
+    pub device_id: String,
+    pub name: Option<String>,
+    pub platform: Option<String>,
+    pub current: bool,
+}
+
+async fn send_verification_email(db: &SurrealDB, mailer: &dyn Mailer, user: &User) -> Result<()> {
+    let email = user
+        .email
+        .clone()
+        .ok_or(Error::BadRequest("Email is required".to_string()))?;
+    let token = OneTimeTokenController::issue(
+        &db.surreal,
+        &user.id.to_string(),
+        OneTimeTokenKind::EmailVerification,
+        Duration::hours(24),
+    )
+    .await?;
+
+    let mail = Mail {
+        to: email,
+        subject: String::from("Verify your email"),
+        body: format!(
+            "Confirm your email address by opening this link, it expires in 24 hours:\n\n{}/verify-email?token={token}",
+            config::get().app_url()
+        ),
+    };
+
+    mailer.send(mail).await
+}
+
 fn get_new_user_email(new_user: &NewUser) -> Result<String> {
     if new_user.email.is_none() {
         return Err(Error::BadRequest("Email is required".to_string()));
     }
 
-    Ok(new_user.clone().email.unwrap().to_lowercase())
+    Ok(normalize_email(new_user.email.as_deref().unwrap()))
 }
 
 #[post("/signup")]
-pub async fn signup(db: Data<SurrealDB>, body: Json<UserPayload>) -> Result<Json<TokenResponse>> {
+pub async fn signup(
+    db: Data<SurrealDB>,
+    mailer: Data<dyn Mailer>,
+    body: Json<UserPayload>,
+) -> Result<Json<TokenResponse>> {
     let payload = body.into_inner();
     let email = get_new_user_email(&payload.user)?;
     let existing_user = UserController::get_by_email(&db.surreal, &email).await?;
@@ -69,6 +160,14 @@ pub async fn signup(db: Data<SurrealDB>, body: Json<UserPayload>) -> Result<Json
 
     let user = UserController::create(&db.surreal, &payload.user).await?;
 
+    // The account works without a verified email, a failed send can be retried from the app
+    if let Err(error) = send_verification_email(&db, mailer.get_ref(), &user).await {
+        log::error!(
+            "Error sending verification email to user {}: {error}",
+            user.id
+        );
+    }
+
     let mut new_device = payload.device.clone();
     new_device.user_id = Some(user.clone().id.to_string());
 
@@ -80,10 +179,17 @@ pub async fn signup(db: Data<SurrealDB>, body: Json<UserPayload>) -> Result<Json
     Ok(Json(token))
 }
 
-#[post("/login")]
-pub async fn login(db: Data<SurrealDB>, body: Json<LoginPayload>) -> Result<Json<TokenResponse>> {
-    let email = body.email.to_lowercase();
-    let existing_user = UserController::get_by_email(&db.surreal, &email).await?;
+async fn sign_in(db: &SurrealDB, user: &User, device: &NewDevice) -> Result<TokenResponse> {
+    let mut new_device = device.clone();
+    new_device.user_id = Some(user.id.to_string());
+
+    let device = DeviceController::create_or_update(&db.surreal, &new_device).await?;
+
+    TokenController::create_or_update(&db.surreal, user, &device.id.to_string()).await
+}
+
+async fn verify_credentials(db: &SurrealDB, email: &str, password: &str) -> Result<User> {
+    let existing_user = UserController::get_by_email(&db.surreal, email).await?;
     let password_hasher = PasswordHasher::new();
 
     if existing_user.is_none() {
@@ -96,16 +202,105 @@ pub async fn login(db: Data<SurrealDB>, body: Json<LoginPayload>) -> Result<Json
         return Err(Error::WrongCredentials);
     }
 
-    let password = user.password_hash.as_ref().unwrap();
-    password_hasher.verify(password, &body.password)?;
+    let password_hash = user.password_hash.as_ref().unwrap();
+    password_hasher.verify(password_hash, password)?;
 
-    let mut new_device = body.device.clone();
-    new_device.user_id = Some(user.clone().id.to_string());
+    Ok(user)
+}
 
-    let device = DeviceController::create_or_update(&db.surreal, &new_device).await?;
+// Wrong credentials count against every key, other errors are not the caller's guess
+pub async fn record_failure(
+    db: &SurrealDB,
+    keys: &[String],
+    policy: &Policy,
+    error: Error,
+) -> Error {
+    if matches!(error, Error::WrongCredentials) {
+        for key in keys {
+            let recorded = ThrottleController::record(&db.surreal, key, policy).await;
+            if let Err(record_error) = recorded {
+                log::warn!("Error recording a failed attempt: {record_error}");
+            }
+        }
+    }
 
-    let token =
-        TokenController::create_or_update(&db.surreal, &user, &device.id.to_string()).await?;
+    error
+}
+
+#[post("/login")]
+pub async fn login(
+    db: Data<SurrealDB>,
+    body: Json<LoginPayload>,
+    req: HttpRequest,
+) -> Result<Json<LoginResponse>> {
+    let email = normalize_email(&body.email);
+
+    // Unknown emails are throttled the same way so lockouts don't reveal which ones exist
+    let ip_key = throttle::key("login", "ip", &client_ip(&req));
+    let email_key = throttle::key("login", "email", &email);
+    let keys = [ip_key, email_key.clone()];
+    ThrottleController::check(&db.surreal, &keys).await?;
+
+    let user = match verify_credentials(&db, &email, &body.password).await {
+        Ok(user) => user,
+        Err(error) => return Err(record_failure(&db, &keys, &throttle::LOGIN, error).await),
+    };
+
+    ThrottleController::clear(&db.surreal, &email_key).await?;
+
+    if TwoFactorController::is_enabled(&db.surreal, &user.id.to_string()).await? {
+        let challenge = OneTimeTokenController::issue(
+            &db.surreal,
+            &user.id.to_string(),
+            OneTimeTokenKind::TwoFactorChallenge,
+            Duration::minutes(5),
+        )
+        .await?;
+
+        return Ok(Json(LoginResponse::Challenge(TwoFactorChallenge {
+            two_factor_required: true,
+            challenge,
+        })));
+    }
+
+    let token = sign_in(&db, &user, &body.device).await?;
+    Ok(Json(LoginResponse::Tokens(token)))
+}
+
+#[post("/login/2fa")]
+pub async fn login_two_factor(
+    db: Data<SurrealDB>,
+    body: Json<TwoFactorLoginPayload>,
+    req: HttpRequest,
+) -> Result<Json<TokenResponse>> {
+    let keys = [throttle::key("login", "ip", &client_ip(&req))];
+    ThrottleController::check(&db.surreal, &keys).await?;
+
+    // A wrong code uses up the challenge too, the password has to be entered again
+    let challenge = OneTimeTokenController::consume(
+        &db.surreal,
+        &body.challenge,
+        OneTimeTokenKind::TwoFactorChallenge,
+    )
+    .await?;
+
+    let id = SurrealId::from_str(&challenge.user_id)?;
+    let user = UserController::get(&db.surreal, &id)
+        .await?
+        .ok_or(Error::WrongCredentials)?;
+
+    // Counted against the user's second factor as well as the address
+    let verified = throttled_two_factor(
+        &db,
+        &user,
+        TwoFactorController::verify(&db.surreal, &user, &body.code),
+    )
+    .await;
+    if let Err(error) = verified {
+        return Err(record_failure(&db, &keys, &throttle::LOGIN, error).await);
+    }
+
+    let token = sign_in(&db, &user, &body.device).await?;
     Ok(Json(token))
 }
 
@@ -114,11 +309,14 @@ pub async fn refresh(
     db: Data<SurrealDB>,
     body: Json<RefreshPayload>,
 ) -> Result<Json<TokenResponse>> {
-    let claims = TokenManager::validate_refresh_token(&db.surreal, &body.refresh_token).await?;
+    let (claims, stored_token) =
+        TokenManager::validate_refresh_token(&db.surreal, &body.refresh_token, &body.device_id)
+            .await?;
     let id = SurrealId::from_str(&claims.sub)?;
     let user = UserController::get(&db.surreal, &id).await?.unwrap();
 
-    let token = TokenController::create_or_update(&db.surreal, &user, &body.device_id).await?;
+    let token =
+        TokenController::create_or_update(&db.surreal, &user, &stored_token.device_id).await?;
     Ok(Json(token))
 }
 
@@ -150,8 +348,14 @@ pub async fn guest(db: Data<SurrealDB>, body: Json<GuestPayload>) -> Result<Json
 pub async fn check_email_exists(
     db: Data<SurrealDB>,
     path: Path<String>,
+    req: HttpRequest,
 ) -> Result<Json<CheckEmailResponse>> {
-    let email = path.into_inner().to_lowercase();
+    // Every lookup counts, not only misses, otherwise the endpoint lists registered emails
+    let ip_key = throttle::key("email-exists", "ip", &client_ip(&req));
+    ThrottleController::check(&db.surreal, std::slice::from_ref(&ip_key)).await?;
+    ThrottleController::record(&db.surreal, &ip_key, &throttle::EMAIL_EXISTS).await?;
+
+    let email = normalize_email(&path.into_inner());
     let existing_user = UserController::get_by_email(&db.surreal, &email).await?;
     Ok(Json(CheckEmailResponse {
         exists: existing_user.is_some(),
@@ -163,3 +367,240 @@ pub async fn validate_token(req: HttpRequest) -> Result<Json<Claims>> {
     let claims = req.extensions().get::<Claims>().unwrap().clone();
     Ok(Json(claims))
 }
+
+#[post("/verify-email")]
+pub async fn verify_email(
+    db: Data<SurrealDB>,
+    body: Json<VerifyEmailPayload>,
+) -> Result<Json<User>> {
+    // Single use, consuming the token deletes it
+    let token = OneTimeTokenController::consume(
+        &db.surreal,
+        &body.token,
+        OneTimeTokenKind::EmailVerification,
+    )
+    .await?;
+    let id = SurrealId::from_str(&token.user_id)?;
+
+    let patch = UserPatch {
+        verified_email: Some(true),
+        ..Default::default()
+    };
+    let user = UserController::update(&db.surreal, &id.to_string(), &patch).await?;
+
+    Ok(Json(user))
+}
+
+#[post("/verify-email/resend")]
+pub async fn resend_verification_email(
+    db: Data<SurrealDB>,
+    mailer: Data<dyn Mailer>,
+    req: HttpRequest,
+) -> Result<Json<String>> {
+    let claims = req.extensions().get::<Claims>().unwrap().clone();
+    let id = SurrealId::from_str(&claims.sub)?;
+    let user = UserController::get(&db.surreal, &id)
+        .await?
+        .ok_or(Error::WrongCredentials)?;
+
+    if user.verified_email == Some(true) {
+        return Err(Error::BadRequest("Email is already verified".to_string()));
+    }
+
+    send_verification_email(&db, mailer.get_ref(), &user).await?;
+
+    Ok(Json(String::from("Success!")))
+}
+
+#[post("/password/forgot")]
+pub async fn forgot_password(
+    db: Data<SurrealDB>,
+    mailer: Data<dyn Mailer>,
+    body: Json<ForgotPasswordPayload>,
+    req: HttpRequest,
+) -> Result<Json<String>> {
+    let email = normalize_email(&body.email);
+
+    // Every request counts, known or not, so nobody can flood an inbox or tell emails apart
+    let keys = [
+        throttle::key("password-reset", "ip", &client_ip(&req)),
+        throttle::key("password-reset", "email", &email),
+    ];
+    ThrottleController::check(&db.surreal, &keys).await?;
+    for key in &keys {
+        ThrottleController::record(&db.surreal, key, &throttle::PASSWORD_RESET).await?;
+    }
+
+    let existing_user = UserController::get_by_email(&db.surreal, &email).await?;
+
+    // Same answer whether or not the email is registered
+    let Some(user) = existing_user.filter(|user| user.password_hash.is_some()) else {
+        return Ok(Json(String::from("Success!")));
+    };
+
+    let token = OneTimeTokenController::issue(
+        &db.surreal,
+        &user.id.to_string(),
+        OneTimeTokenKind::PasswordReset,
+        Duration::hours(1),
+    )
+    .await?;
+
+    let mail = Mail {
+        to: email,
+        subject: String::from("Reset your password"),
+        body: format!(
+            "Choose a new password by opening this link, it expires in 1 hour:\n\n{}/reset-password?token={token}\n\nIf you did not ask for a reset you can ignore this email.",
+            config::get().app_url()
+        ),
+    };
+    // A failed send is ours to look into, the reply can't differ from unknown emails
+    if let Err(error) = mailer.send(mail).await {
+        log::error!(
+            "Error sending password reset email to user {}: {error}",
+            user.id
+        );
+    }
+
+    Ok(Json(String::from("Success!")))
+}
+
+#[post("/password/reset")]
+pub async fn reset_password(
+    db: Data<SurrealDB>,
+    body: Json<ResetPasswordPayload>,
+) -> Result<Json<String>> {
+    if body.password.is_empty() {
+        return Err(Error::BadRequest("password is required".to_string()));
+    }
+
+    let reset_token =
+        OneTimeTokenController::consume(&db.surreal, &body.token, OneTimeTokenKind::PasswordReset)
+            .await?;
+
+    let password_hasher = PasswordHasher::new();
+    let patch = UserPatch {
+        password_hash: Some(password_hasher.derive(&body.password)?),
+        // Receiving the reset link proves the user owns the address
+        verified_email: Some(true),
+        ..Default::default()
+    };
+    UserController::update(&db.surreal, &reset_token.user_id, &patch).await?;
+
+    TokenController::delete_by_user(&db.surreal, &reset_token.user_id).await?;
+
+    Ok(Json(String::from("Success!")))
+}
+
+#[post("/upgrade")]
+pub async fn upgrade(
+    db: Data<SurrealDB>,
+    mailer: Data<dyn Mailer>,
+    body: Json<UpgradePayload>,
+    credentials: BearerAuth,
+    req: HttpRequest,
+) -> Result<Json<TokenResponse>> {
+    let claims = req.extensions().get::<Claims>().unwrap().clone();
+    let id = SurrealId::from_str(&claims.sub)?;
+    let payload = body.into_inner();
+
+    let email = normalize_email(&payload.email);
+    let mut invalid = BTreeMap::new();
+    if email.is_empty() {
+        invalid.insert("email".to_string(), "is required".to_string());
+    }
+    if payload.password.is_empty() {
+        invalid.insert("password".to_string(), "is required".to_string());
+    }
+    if !invalid.is_empty() {
+        return Err(Error::InvalidFields(invalid));
+    }
+
+    let existing_user = UserController::get_by_email(&db.surreal, &email).await?;
+    if existing_user.is_some() {
+        return Err(Error::EmailInUse);
+    }
+
+    let current_token = TokenController::get_by_access_token(&db.surreal, credentials.token())
+        .await?
+        .ok_or(Error::TokenMismatch)?;
+
+    let password_hasher = PasswordHasher::new();
+    let password_hash = password_hasher.derive(&payload.password)?;
+    let user =
+        UserController::upgrade_guest(&db.surreal, &id, &email, &password_hash, payload.name)
+            .await?;
+
+    DeviceController::release_guest(&db.surreal, &user.id.to_string()).await?;
+
+    if let Err(error) = send_verification_email(&db, mailer.get_ref(), &user).await {
+        log::error!(
+            "Error sending verification email to user {}: {error}",
+            user.id
+        );
+    }
+
+    let token =
+        TokenController::create_or_update(&db.surreal, &user, &current_token.device_id).await?;
+
+    Ok(Json(token))
+}
+
+#[post("/logout")]
+pub async fn logout(db: Data<SurrealDB>, credentials: BearerAuth) -> Result<Json<String>> {
+    TokenController::delete_by_access_token(&db.surreal, credentials.token()).await?;
+    Ok(Json(String::from("Success!")))
+}
+
+#[post("/logout-all")]
+pub async fn logout_all(db: Data<SurrealDB>, req: HttpRequest) -> Result<Json<String>> {
+    let claims = req.extensions().get::<Claims>().unwrap().clone();
+    let id = SurrealId::from_str(&claims.sub)?;
+
+    TokenController::delete_by_user(&db.surreal, &id.to_string()).await?;
+    Ok(Json(String::from("Success!")))
+}
+
+#[get("/sessions")]
+pub async fn get_sessions(
+    db: Data<SurrealDB>,
+    credentials: BearerAuth,
+    req: HttpRequest,
+) -> Result<Json<Vec<Session>>> {
+    let claims = req.extensions().get::<Claims>().unwrap().clone();
+    let id = SurrealId::from_str(&claims.sub)?;
+    let tokens = TokenController::get_sessions(&db.surreal, &id.to_string()).await?;
+
+    let mut sessions = Vec::with_capacity(tokens.len());
+    for token in tokens {
+        let device = DeviceController::get(&db.surreal, &token.device_id).await?;
+
+        sessions.push(Session {
+            current: token.access_token == credentials.token(),
+            name: device.as_ref().and_then(|device| device.name.clone()),
+            platform: device.and_then(|device| device.platform),
+            device_id: token.device_id,
+        });
+    }
+
+    Ok(Json(sessions))
+}
+
+#[delete("/sessions/{device_id}")]
+pub async fn revoke_session(
+    db: Data<SurrealDB>,
+    path: Path<String>,
+    req: HttpRequest,
+) -> Result<Json<String>> {
+    let claims = req.extensions().get::<Claims>().unwrap().clone();
+    let id = SurrealId::from_str(&claims.sub)?;
+    let device_id = path.into_inner();
+
+    let revoked =
+        TokenController::delete_by_device(&db.surreal, &id.to_string(), &device_id).await?;
+    if !revoked {
+        return Err(Error::NotFound("session".to_string()));
+    }
+
+    Ok(Json(String::from("Success!")))
+}
@@ -1,20 +1,21 @@
-use std::env;
+use actix_web::HttpRequest;
+use serde::{Deserialize, Deserializer, Serialize, de};
 
-use serde::{Deserialize, Serialize};
+use crate::config;
 
+pub mod admin;
+pub mod api_key;
 pub mod auth;
 pub mod device;
+pub mod request_id;
+pub mod routes;
 pub mod storage;
 pub mod transcription;
+pub mod two_factor;
 pub mod user;
 
 pub fn get_default_webhook_base() -> String {
-    let project_env = env::var("PROJECT_ENV").unwrap_or(String::from("development"));
-    let base_url = match project_env.as_str() {
-        "prod" => "https://echo-server.fly.dev".to_string(),
-        "development" => "https://8a40d8967902.ngrok-free.app".to_string(),
-        _ => "".to_string(),
-    };
+    let base_url = config::get().server.public_url.trim_end_matches('/');
 
     format!("{base_url}/webhook")
 }
@@ -25,6 +26,26 @@ pub fn make_default_webhook_url(tool_type: &str) -> String {
     format!("{base_url}/{tool_type}/status")
 }
 
+// Forwarding headers can be sent by anyone, so only the one our proxy sets is trusted
+pub fn client_ip(req: &HttpRequest) -> String {
+    let forwarded = config::get()
+        .server
+        .trusted_proxy_header
+        .as_deref()
+        .and_then(|header| req.headers().get(header))
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|value| !value.is_empty());
+
+    match forwarded {
+        Some(ip) => ip.to_string(),
+        None => req
+            .peer_addr()
+            .map(|addr| addr.ip().to_string())
+            .unwrap_or(String::from("unknown")),
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct PaginationParameters<T = ()> {
     #[serde(default)]
@@ -39,6 +60,65 @@ fn default_limit() -> usize {
     50
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SortDirection {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl SortDirection {
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            SortDirection::Asc => "ASC",
+            SortDirection::Desc => "DESC",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: usize,
+    pub next_offset: Option<usize>,
+}
+
+impl<T> Page<T> {
+    pub fn new<F>(items: Vec<T>, total: usize, pagination: &PaginationParameters<F>) -> Self {
+        let end = pagination.offset + items.len();
+        let next_offset = if end < total { Some(end) } else { None };
+
+        Page {
+            items,
+            total,
+            next_offset,
+        }
+    }
+}
+
+// Flattened filters receive query string values as text, so booleans have to be parsed by hand
+pub fn deserialize_optional_bool<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<bool>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum BoolOrString {
+        Bool(bool),
+        String(String),
+    }
+
+    match Option::<BoolOrString>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(BoolOrString::Bool(value)) => Ok(Some(value)),
+        Some(BoolOrString::String(value)) => value.parse().map(Some).map_err(de::Error::custom),
+    }
+}
+
 impl<T> Default for PaginationParameters<T> {
     fn default() -> Self {
         Self {
@@ -0,0 +1,73 @@
+use actix_web::{
+    HttpResponse,
+    body::{BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue},
+    middleware::Next,
+};
+use rand::distributions::{Alphanumeric, DistString};
+
+use crate::error::{ErrorBody, ErrorEnvelope};
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+// A proxy in front of us may already have tagged the request, otherwise we make one up
+fn incoming_or_new(req: &ServiceRequest) -> String {
+    req.headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| {
+            !value.is_empty()
+                && value.len() <= 64
+                && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+        .map(str::to_owned)
+        .unwrap_or_else(|| Alphanumeric.sample_string(&mut rand::thread_rng(), 16))
+}
+
+// Server errors are logged with the request id and the client only gets the id and a code
+fn error_response(error: &actix_web::Error, request_id: &str) -> HttpResponse {
+    if let Some(error) = error.as_error::<crate::error::Error>() {
+        if error.is_internal() {
+            log::error!("[{request_id}] {}: {error}", error.code());
+        }
+        return error.response(Some(request_id));
+    }
+
+    let status = error.as_response_error().status_code();
+    if status.is_server_error() {
+        log::error!("[{request_id}] {error}");
+    }
+
+    let mut body = ErrorBody::from_status(status, error.to_string());
+    body.request_id = Some(request_id);
+
+    HttpResponse::build(status).json(ErrorEnvelope { error: body })
+}
+
+// Tags every request with an id that comes back in the X-Request-Id header and in error bodies
+pub async fn request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let id = incoming_or_new(&req);
+    let http_req = req.request().clone();
+
+    let mut res = match next.call(req).await {
+        Ok(res) => match res.response().error() {
+            Some(error) => {
+                let response = error_response(error, &id);
+                res.into_response(response)
+            }
+            None => res.map_into_boxed_body(),
+        },
+        // Middleware like the bearer validator fails the whole service call instead
+        Err(error) => ServiceResponse::new(http_req, error_response(&error, &id)),
+    };
+
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        res.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+
+    Ok(res)
+}
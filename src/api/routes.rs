@@ -0,0 +1,246 @@
+use actix_web::{
+    Error, HttpMessage,
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    error::JsonPayloadError,
+    middleware::{Next, from_fn},
+    web::{Data, JsonConfig, ServiceConfig, scope},
+};
+use actix_web_grants::authorities::AttachAuthorities;
+use actix_web_httpauth::{extractors::bearer::BearerAuth, middleware::HttpAuthentication};
+
+use crate::{
+    api::{
+        admin::{get_user as admin_get_user, list_users, set_user_roles},
+        api_key::{create_api_key, delete_api_key, get_api_keys},
+        auth::{
+            check_email_exists, forgot_password, get_sessions, guest, login, login_two_factor,
+            logout, logout_all, refresh, resend_verification_email, reset_password, revoke_session,
+            signup, upgrade, validate_token, verify_email,
+        },
+        storage::{presign_get, presign_put},
+        transcription::{
+            delete_transcription, diarize_webhook, export_transcription, get_transcription,
+            get_user_transcriptions, retry_transcription, search_transcriptions, transcribe,
+            transcribe_raw_only,
+        },
+        two_factor::{
+            confirm_two_factor, disable_two_factor, regenerate_recovery_codes, setup_two_factor,
+        },
+        user::{change_password, delete_user, get_user, update_user},
+    },
+    model::{
+        api_key::{API_KEY_PREFIX, ApiKeyController},
+        token::{Claims, ClaimsOrigin, TokenManager},
+    },
+    repo::surreal::SurrealDB,
+};
+
+async fn validator(
+    req: ServiceRequest,
+    credentials: BearerAuth,
+) -> Result<ServiceRequest, (Error, ServiceRequest)> {
+    let db = req.app_data::<Data<SurrealDB>>().unwrap();
+    let token = credentials.token();
+    let result = if token.starts_with(API_KEY_PREFIX) {
+        ApiKeyController::validate(&db.surreal, token)
+            .await
+            .map(|claims| Claims {
+                origin: ClaimsOrigin::ApiKey,
+                ..claims
+            })
+    } else {
+        TokenManager::validate_access_token(&db.surreal, token).await
+    };
+
+    match result {
+        Ok(claims) => {
+            req.attach(claims.permissions.clone());
+            req.extensions_mut().insert(claims);
+            Ok(req)
+        }
+        Err(_) => Err((crate::error::Error::Unauthorized.into(), req)),
+    }
+}
+
+// Account, session and key management need a signed in user, an API key can't reach them
+async fn session_only(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let origin = req.extensions().get::<Claims>().map(|claims| claims.origin);
+
+    if origin != Some(ClaimsOrigin::Session) {
+        return Err(
+            crate::error::Error::Forbidden("API keys can't access this route".to_string()).into(),
+        );
+    }
+
+    next.call(req).await
+}
+
+// Unreadable and oversized JSON bodies come back as our own errors instead of actix's text ones
+fn json_config() -> JsonConfig {
+    JsonConfig::default().error_handler(|error, _| match error {
+        JsonPayloadError::Overflow { limit }
+        | JsonPayloadError::OverflowKnownLength { limit, .. } => {
+            crate::error::Error::PayloadTooLarge(limit).into()
+        }
+        error => crate::error::Error::InvalidBody(error.to_string()).into(),
+    })
+}
+
+// Every route of the server, shared by main and anything that builds the App on its own
+pub fn configure(cfg: &mut ServiceConfig) {
+    let auth = HttpAuthentication::bearer(validator);
+
+    cfg.app_data(json_config());
+    cfg.service(
+        scope("/auth")
+            .service(login)
+            .service(login_two_factor)
+            .service(signup)
+            .service(refresh)
+            .service(guest)
+            .service(check_email_exists)
+            .service(verify_email)
+            .service(forgot_password)
+            .service(reset_password),
+    )
+    .service(
+        scope("/api")
+            .wrap(auth)
+            .service(
+                scope("/auth")
+                    .wrap(from_fn(session_only))
+                    .service(validate_token)
+                    .service(resend_verification_email)
+                    .service(upgrade)
+                    .service(logout)
+                    .service(logout_all)
+                    .service(get_sessions)
+                    .service(revoke_session)
+                    .service(setup_two_factor)
+                    .service(confirm_two_factor)
+                    .service(disable_two_factor)
+                    .service(regenerate_recovery_codes),
+            )
+            .service(
+                scope("/user")
+                    .wrap(from_fn(session_only))
+                    .service(get_user)
+                    .service(update_user)
+                    .service(change_password)
+                    .service(delete_user),
+            )
+            .service(
+                scope("/keys")
+                    .wrap(from_fn(session_only))
+                    .service(get_api_keys)
+                    .service(create_api_key)
+                    .service(delete_api_key),
+            )
+            .service(
+                scope("/admin")
+                    .service(list_users)
+                    .service(admin_get_user)
+                    .service(set_user_roles),
+            )
+            .service(scope("/storage").service(presign_put).service(presign_get))
+            .service(
+                scope("/transcription")
+                    .service(get_user_transcriptions)
+                    .service(search_transcriptions)
+                    .service(get_transcription)
+                    .service(delete_transcription)
+                    .service(export_transcription)
+                    .service(transcribe_raw_only)
+                    .service(transcribe)
+                    .service(retry_transcription),
+            ),
+    )
+    .service(scope("webhook").service(diarize_webhook));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::{App, http::StatusCode, test};
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::{
+        config,
+        connector::mailer::{CaptureMailer, Mailer},
+    };
+
+    #[actix_web::test]
+    async fn signs_up_and_logs_in() {
+        config::init_for_tests();
+        let db = SurrealDB::memory().await.unwrap();
+        let mailer = Arc::new(CaptureMailer::default());
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db))
+                .app_data(Data::from(mailer.clone() as Arc<dyn Mailer>))
+                .configure(configure),
+        )
+        .await;
+
+        let signup = test::TestRequest::post()
+            .uri("/auth/signup")
+            .set_json(json!({
+                "user": {
+                    "email": " Ada@Example.com ",
+                    "password": "correct horse",
+                    "name": "Ada",
+                    "avatarSeed": "ada",
+                },
+                "device": { "id": "signup-device" },
+            }))
+            .to_request();
+        let res = test::call_service(&app, signup).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let sent = mailer.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, "ada@example.com");
+
+        let wrong_password = test::TestRequest::post()
+            .uri("/auth/login")
+            .set_json(json!({
+                "email": "ada@example.com",
+                "password": "wrong horse",
+                "device": { "id": "login-device" },
+            }))
+            .to_request();
+        let res = test::call_service(&app, wrong_password).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let login = test::TestRequest::post()
+            .uri("/auth/login")
+            .set_json(json!({
+                "email": "ADA@example.com",
+                "password": "correct horse",
+                "device": { "id": "login-device" },
+            }))
+            .to_request();
+        let res = test::call_service(&app, login).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let body: Value = test::read_body_json(res).await;
+        let token = body["token"].as_str().unwrap();
+        assert!(body["refreshToken"].is_string());
+
+        let me = test::TestRequest::get()
+            .uri("/api/user/me")
+            .insert_header(("Authorization", format!("Bearer {token}")))
+            .to_request();
+        let res = test::call_service(&app, me).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let user: Value = test::read_body_json(res).await;
+        assert_eq!(user["email"], "ada@example.com");
+    }
+}
@@ -1,5 +1,3 @@
-use std::env;
-
 use actix_web::{
     get,
     web::{Json, Query},
@@ -15,7 +13,7 @@ use rusoto_s3::{
     util::{PreSignedRequest, PreSignedRequestOption},
 };
 
-use crate::error::Result;
+use crate::{config, connector::backblaze::BackBlaze, error::Result};
 
 struct AwsConfig {
     region: Region,
@@ -38,10 +36,7 @@ pub struct PresignedUrlResponse {
 async fn aws_config() -> AwsConfig {
     let provider = ChainProvider::new();
     let credentials = provider.credentials().await.unwrap();
-    let region = Region::Custom {
-        name: "us-west-004".to_string(),
-        endpoint: "s3.us-west-004.backblazeb2.com".to_string(),
-    };
+    let region = BackBlaze::region();
 
     let options = PreSignedRequestOption {
         expires_in: std::time::Duration::from_secs(300),
@@ -57,19 +52,20 @@ async fn aws_config() -> AwsConfig {
 #[get("/sign/put")]
 pub async fn presign_put(query: Query<SignParams>) -> Result<Json<PresignedUrlResponse>> {
     let payload = query.into_inner();
-    let bucket = env::var("BUCKET").unwrap();
-    let config = aws_config().await;
+    let bucket = &config::get().storage.bucket;
+    let aws_config = aws_config().await;
     let req = PutObjectRequest {
         bucket: bucket.to_string(),
         key: payload.key.clone(),
         ..Default::default()
     };
 
-    let signed_url = req.get_presigned_url(&config.region, &config.credentials, &config.options);
-    let url = format!(
-        "https://f004.backblazeb2.com/file/{bucket}/{}",
-        &payload.key
+    let signed_url = req.get_presigned_url(
+        &aws_config.region,
+        &aws_config.credentials,
+        &aws_config.options,
     );
+    let url = BackBlaze::file_url(&payload.key);
 
     let res = PresignedUrlResponse { signed_url, url };
 
@@ -79,19 +75,20 @@ pub async fn presign_put(query: Query<SignParams>) -> Result<Json<PresignedUrlRe
 #[get("/sign/get")]
 pub async fn presign_get(query: Query<SignParams>) -> Result<Json<PresignedUrlResponse>> {
     let payload = query.into_inner();
-    let bucket = env::var("BUCKET").unwrap();
-    let config = aws_config().await;
+    let bucket = &config::get().storage.bucket;
+    let aws_config = aws_config().await;
     let req = GetObjectRequest {
         bucket: bucket.to_string(),
         key: payload.key.clone(),
         ..Default::default()
     };
 
-    let signed_url = req.get_presigned_url(&config.region, &config.credentials, &config.options);
-    let url = format!(
-        "https://f004.backblazeb2.com/file/{bucket}/{}",
-        &payload.key
+    let signed_url = req.get_presigned_url(
+        &aws_config.region,
+        &aws_config.credentials,
+        &aws_config.options,
     );
+    let url = BackBlaze::file_url(&payload.key);
 
     let res = PresignedUrlResponse { signed_url, url };
 
@@ -1,41 +1,109 @@
-use std::str::FromStr;
+use std::{future::Future, pin::Pin, str::FromStr};
 
 use actix_web::{
-    HttpMessage, HttpRequest, get, post,
-    web::{Data, Json, Path, Query},
+    FromRequest, HttpMessage, HttpRequest, HttpResponse, delete,
+    dev::Payload,
+    get,
+    http::header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    post,
+    web::{Data, Json, Query},
+};
+use actix_web_grants::{
+    authorities::{AuthDetails, AuthoritiesCheck},
+    protect,
 };
 use serde::{Deserialize, Serialize};
-use surrealdb::{Surreal, engine::remote::ws::Client};
-use surrealitos::SurrealId;
+use surrealitos::{SurrealId, extract_id};
 
 use crate::{
-    api::{PaginationParameters, make_default_webhook_url},
+    api::{Page, PaginationParameters},
     connector::{
-        HttpMethod,
         backblaze::BackBlaze,
-        mistral::{Mistral, TranscriptionResponse},
-        modal::{
-            BaseParameters, DiarizationInput, ModalAI, ResultOutput, Status as ModalStatus,
-            ToolAsyncIO,
-        },
+        llm,
+        mistral::Mistral,
+        modal::{ResultOutput, Status as ModalStatus},
         reverb::Reverb,
     },
     error::{Error, Result},
+    export::{self, ExportFormat},
     model::{
-        Controller,
+        Controller, LLMProvider,
+        job::{JobController, JobPatch, JobStatus},
+        role,
         token::Claims,
         transcription::{
-            NewTranscription, Segment, Status, Transcription, TranscriptionController,
-            TranscriptionPatch,
+            Failure, NewTranscription, SearchResult, Segment, Status, Step, Transcription,
+            TranscriptionController, TranscriptionFilters, TranscriptionPatch,
         },
         user::UserController,
     },
+    pipeline,
     repo::surreal::SurrealDB,
 };
 
+// Transcription from the `{id}` path segment, only resolves for its owner.
+// Every route that reads or changes a single transcription takes this instead of loading it by id.
+pub struct OwnedTranscription(pub Transcription);
+
+impl OwnedTranscription {
+    async fn load(req: HttpRequest) -> Result<Self> {
+        let db = req
+            .app_data::<Data<SurrealDB>>()
+            .ok_or(Error::StoreData("database".to_string()))?
+            .clone();
+        let claims = req
+            .extensions()
+            .get::<Claims>()
+            .cloned()
+            .ok_or(Error::Unauthorized)?;
+        let user_id = SurrealId::from_str(&claims.sub)?;
+
+        // Unknown, malformed and foreign ids all look the same to the client
+        let id: SurrealId = req
+            .match_info()
+            .get("id")
+            .and_then(|id| id.parse().ok())
+            .ok_or(Error::NotFound("transcription".to_string()))?;
+
+        TranscriptionController::get_owned(&db.surreal, &id, &user_id)
+            .await?
+            .map(OwnedTranscription)
+            .ok_or(Error::NotFound("transcription".to_string()))
+    }
+}
+
+impl FromRequest for OwnedTranscription {
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self>>>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        Box::pin(Self::load(req.clone()))
+    }
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct FilePayload {
     file: String,
+    #[serde(alias = "llmProvider")]
+    llm_provider: Option<LLMProvider>,
+    llm: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SearchParameters {
+    q: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ExportParameters {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+// Send `{}` to retry from the first missing step
+#[derive(Deserialize, Serialize)]
+pub struct RetryPayload {
+    stage: Option<Step>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -46,9 +114,9 @@ pub struct DiarizeOutput {
 #[get("/all")]
 pub async fn get_user_transcriptions(
     db: Data<SurrealDB>,
-    query: Query<PaginationParameters>,
+    query: Query<PaginationParameters<TranscriptionFilters>>,
     req: HttpRequest,
-) -> Result<Json<Vec<Transcription>>> {
+) -> Result<Json<Page<Transcription>>> {
     let claims = req.extensions().get::<Claims>().unwrap().clone();
     let user_id = SurrealId::from_str(&claims.sub)?;
     let user_option = UserController::get(&db.surreal, &user_id).await?;
@@ -57,27 +125,107 @@ pub async fn get_user_transcriptions(
         return Err(Error::WrongCredentials);
     }
 
-    let transcriptions = user_option
+    let pagination = query.into_inner();
+    let (transcriptions, total) = user_option
         .unwrap()
-        .get_transcriptions(&db.surreal, &query.into_inner())
+        .get_transcriptions(&db.surreal, &pagination)
         .await?;
 
-    Ok(Json(transcriptions))
+    Ok(Json(Page::new(transcriptions, total, &pagination)))
+}
+
+#[get("/search")]
+pub async fn search_transcriptions(
+    db: Data<SurrealDB>,
+    query: Query<PaginationParameters<SearchParameters>>,
+    req: HttpRequest,
+) -> Result<Json<Vec<SearchResult>>> {
+    let claims = req.extensions().get::<Claims>().unwrap().clone();
+    let user_id = SurrealId::from_str(&claims.sub)?;
+
+    let pagination = query.into_inner();
+    let search = pagination
+        .filters
+        .filter(|search| !search.q.trim().is_empty())
+        .ok_or(Error::BadRequest("q is required".to_string()))?;
+
+    let hits = TranscriptionController::search(
+        &db.surreal,
+        &user_id,
+        search.q.trim(),
+        pagination.limit,
+        pagination.offset,
+    )
+    .await?;
+
+    let results = hits
+        .into_iter()
+        .map(|hit| hit.into_result(&search.q))
+        .collect();
+
+    Ok(Json(results))
 }
 
 #[get("/{id}")]
-pub async fn get_transcription(
+pub async fn get_transcription(transcription: OwnedTranscription) -> Result<Json<Transcription>> {
+    Ok(Json(transcription.0))
+}
+
+#[get("/{id}/export")]
+pub async fn export_transcription(
+    transcription: OwnedTranscription,
+    query: Query<ExportParameters>,
+) -> Result<HttpResponse> {
+    let transcription = transcription.0;
+    let format = query.into_inner().format;
+    let body = export::export(&transcription, &format)?;
+
+    let file_name = format!(
+        "transcription-{}.{}",
+        extract_id(&transcription.id.to_string(), "transcription"),
+        format.extension()
+    );
+
+    Ok(HttpResponse::Ok()
+        .insert_header((CONTENT_TYPE, format.content_type()))
+        .insert_header((
+            CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{file_name}\""),
+        ))
+        .body(body))
+}
+
+#[delete("/{id}")]
+pub async fn delete_transcription(
     db: Data<SurrealDB>,
-    path: Path<String>,
-) -> Result<Json<Transcription>> {
-    let id: SurrealId = path.into_inner().parse()?;
-    let transcription_opt = TranscriptionController::get(&db.surreal, &id).await?;
-    let transcription = transcription_opt.ok_or(Error::NotFound("transcription".to_string()))?;
+    transcription: OwnedTranscription,
+) -> Result<Json<String>> {
+    let transcription = transcription.0;
+    let reverb = Reverb::new();
 
-    Ok(Json(transcription))
+    // The row goes either way, a failed storage delete only leaves an orphaned object behind
+    let deleted = match &transcription.audio_file {
+        Some(audio_file) => BackBlaze::delete_file(audio_file).await,
+        None => Ok(()),
+    };
+    if let Err(error) = deleted {
+        log::error!(
+            "Error deleting the audio of transcription {}: {error}",
+            transcription.id
+        );
+    }
+
+    TranscriptionController::delete(&db.surreal, &transcription.id).await?;
+
+    let _ = reverb
+        .notify_update("transcription/deleted", transcription)
+        .await;
+
+    Ok(Json(String::from("Success!")))
 }
 
 #[post("/raw")]
+#[protect(role::TRANSCRIBE)]
 pub async fn transcribe_raw_only(
     db: Data<SurrealDB>,
     body: Json<FilePayload>,
@@ -120,112 +268,125 @@ pub async fn transcribe_raw_only(
     Ok(Json(transcription))
 }
 
-async fn diarize_async(diarize_input: &DiarizationInput) -> Result<ToolAsyncIO> {
-    // TODO: handle calls
-    let modal = ModalAI::new();
-
-    let output = modal
-        .run::<DiarizationInput, ToolAsyncIO>(HttpMethod::Post, "diarize/initiate", diarize_input)
-        .await?;
-
-    Ok(output)
-}
+// Without the summarize permission the pipeline stops once the transcript is diarized
+#[post("/transcribe")]
+#[protect(role::TRANSCRIBE)]
+pub async fn transcribe(
+    db: Data<SurrealDB>,
+    body: Json<FilePayload>,
+    req: HttpRequest,
+    details: AuthDetails,
+) -> Result<Json<Transcription>> {
+    let claims = req.extensions().get::<Claims>().unwrap().clone();
+    let user_id = SurrealId::from_str(&claims.sub)?;
+    let user = UserController::get(&db.surreal, &user_id)
+        .await?
+        .ok_or(Error::WrongCredentials)?;
 
-async fn transcribe_async(
-    client: &Surreal<Client>,
-    transcription_id: &SurrealId,
-    file_url: &str,
-    diarize: bool,
-) -> Result<TranscriptionResponse> {
-    let mistral = Mistral::new();
-    let reverb = Reverb::new();
-    let raw_transcription = mistral.transcribe(file_url, true).await?;
-    println!(
-        "raw_transcription: {}",
-        serde_json::to_string_pretty(&raw_transcription)?
-    );
+    let payload = body.into_inner();
 
-    let new_status = if diarize {
-        Status::Diarizing
+    // The request can override the provider and model the user picked in their settings
+    let llm = if details.has_authority(role::SUMMARIZE) {
+        Some(llm::select(
+            payload.llm_provider.or(user.llm_provider),
+            payload.llm.or(user.llm_model),
+        )?)
     } else {
-        Status::Done
+        None
     };
 
-    let patch = TranscriptionPatch {
-        raw: Some(raw_transcription.text.to_string()),
-        language: raw_transcription.language.clone(),
-        status: Some(new_status),
+    let reverb = Reverb::new();
+
+    // The picked LLM is what marks the transcription for the Summarize step
+    let new_transcription = NewTranscription {
+        status: Some(Status::Transcribing),
+        audio_file: Some(BackBlaze::sanitize_url(&payload.file)),
+        llm: llm.as_ref().map(|llm| llm.model().to_string()),
+        llm_provider: llm.as_ref().map(|llm| llm.provider()),
+        user: Some(user_id),
         ..Default::default()
     };
 
-    let transcription =
-        TranscriptionController::update(client, &transcription_id.to_string(), &patch).await?;
+    let transcription = TranscriptionController::create(&db.surreal, &new_transcription).await?;
+
+    // The worker picks the job up in the background without blocking the API response for user
+    JobController::enqueue(&db.surreal, &transcription.id, Step::Transcribe).await?;
 
     let _ = reverb
-        .notify_update("transcription/updated", transcription)
+        .notify_update("transcription/created", transcription.clone())
         .await;
 
-    if diarize {
-        let segments = raw_transcription.segments.clone();
-        let diarize_input = DiarizationInput {
-            audio: file_url.to_string(),
-            segments: segments.unwrap_or(vec![]),
-            base: BaseParameters {
-                webhook_url: make_default_webhook_url("diarize"),
-                job_id: transcription_id.to_string(),
-            },
-        };
+    Ok(Json(transcription))
+}
 
-        tokio::spawn(async move { diarize_async(&diarize_input).await });
+// Earliest step whose output is missing from the stored transcription
+fn first_missing_step(transcription: &Transcription) -> Step {
+    if transcription.raw.is_none() || transcription.segments.is_none() {
+        Step::Transcribe
+    } else if transcription.diarized.is_none() {
+        Step::Diarize
+    } else {
+        Step::Summarize
     }
-
-    Ok(raw_transcription)
 }
 
-#[post("/transcribe")]
-pub async fn transcribe(
+#[post("/{id}/retry")]
+#[protect(role::TRANSCRIBE)]
+pub async fn retry_transcription(
     db: Data<SurrealDB>,
-    body: Json<FilePayload>,
-    req: HttpRequest,
+    transcription: OwnedTranscription,
+    body: Json<RetryPayload>,
+    details: AuthDetails,
 ) -> Result<Json<Transcription>> {
-    let claims = req.extensions().get::<Claims>().unwrap().clone();
-    let user_id = SurrealId::from_str(&claims.sub)?;
+    let transcription = transcription.0;
+    let id = transcription.id.clone();
 
-    let payload = body.into_inner();
-    let mut file_url = payload.file;
+    let missing_step = first_missing_step(&transcription);
+    let step = match body.into_inner().stage {
+        // Steps can only rerun once everything before them is stored
+        Some(stage) if stage > missing_step => {
+            return Err(Error::BadRequest(format!(
+                "The {missing_step:?} step has to finish before {stage:?} can run"
+            )));
+        }
+        Some(stage) => stage,
+        None => missing_step,
+    };
 
-    if !file_url.contains("Authorization=") {
-        let read_blaze_token = BackBlaze::get_read_auth_token().await?;
-        let separator = if file_url.contains('?') { "&" } else { "?" };
-        file_url = format!(
-            "{}{}Authorization={}",
-            file_url, separator, read_blaze_token
-        );
+    // Summaries are optional on top of transcribing, so this one is checked here
+    if step == Step::Summarize && !details.has_authority(role::SUMMARIZE) {
+        return Err(Error::Forbidden(format!(
+            "Missing the {} permission",
+            role::SUMMARIZE
+        )));
     }
 
-    let reverb = Reverb::new();
-
-    let sanitized_url = if let Some(question_mark_pos) = file_url.find('?') {
-        file_url[..question_mark_pos].to_string()
-    } else {
-        file_url.clone()
-    };
-
-    let new_transcription = NewTranscription {
-        status: Some(Status::Transcribing),
-        audio_file: Some(sanitized_url),
-        user: Some(user_id),
-        ..Default::default()
-    };
+    let active_jobs = JobController::get_active(&db.surreal, &id).await?;
+    if active_jobs
+        .iter()
+        .any(|job| job.status != JobStatus::Waiting)
+    {
+        return Err(Error::BadRequest(
+            "Transcription is already being processed".to_string(),
+        ));
+    }
 
-    let transcription = TranscriptionController::create(&db.surreal, &new_transcription).await?;
+    // A webhook that never arrived leaves its job Waiting, the retry replaces it
+    for job in active_jobs {
+        let patch = JobPatch {
+            status: Some(JobStatus::Failed),
+            last_error: Some("Replaced by a retry".to_string()),
+            ..Default::default()
+        };
+        JobController::update(&db.surreal, &job.id.to_string(), &patch).await?;
+    }
 
-    let id = transcription.id.clone();
-    // fire and forget to continue in bg without blocking the API response for user
-    tokio::spawn(async move { transcribe_async(&db.surreal, &id, &file_url, true).await });
+    let transcription = TranscriptionController::restart(&db.surreal, &id, &step).await?;
+    JobController::enqueue(&db.surreal, &id, step).await?;
 
+    let reverb = Reverb::new();
     let _ = reverb
-        .notify_update("transcription/created", transcription.clone())
+        .notify_update("transcription/updated", transcription.clone())
         .await;
 
     Ok(Json(transcription))
@@ -238,13 +399,26 @@ pub async fn diarize_webhook(
 ) -> Result<Json<String>> {
     let reverb = Reverb::new();
     let result = body.into_inner();
-    if result.status == ModalStatus::Success {
-        let id = result.id.clone().unwrap();
-        if let Some(data) = result.data {
-            // TODO: spawn summarization here
+    let id = result
+        .id
+        .clone()
+        .ok_or(Error::BadRequest("Missing job id".to_string()))?;
+    let job = JobController::get_dispatched(&db.surreal, &id, Step::Diarize).await?;
+
+    let (job_status, failure) = match (result.status, result.data) {
+        (ModalStatus::Success, Some(data)) => {
+            // Transcriptions created without the summarize permission have no LLM and end here
+            let summarize = TranscriptionController::get(&db.surreal, &id)
+                .await?
+                .is_some_and(|transcription| transcription.llm_provider.is_some());
+            let status = if summarize {
+                Status::Summarizing
+            } else {
+                Status::Done
+            };
 
             let patch = TranscriptionPatch {
-                status: Some(Status::Summarizing),
+                status: Some(status),
                 diarized: Some(data.segments),
                 ..Default::default()
             };
@@ -254,7 +428,41 @@ pub async fn diarize_webhook(
             let _ = reverb
                 .notify_update("transcription/updated", transcription)
                 .await;
+
+            if summarize {
+                JobController::enqueue(&db.surreal, &id, Step::Summarize).await?;
+            }
+            (JobStatus::Done, None)
         }
+        (ModalStatus::Success, None) => {
+            let failure = Failure::new(
+                Step::Diarize,
+                "modal_empty_result",
+                "Diarization finished without segments",
+            );
+            (JobStatus::Failed, Some(failure))
+        }
+        (ModalStatus::Error, _) | (ModalStatus::Cancelled, _) => {
+            let message = result
+                .error
+                .unwrap_or("Diarization did not finish".to_string());
+            let failure = Failure::new(Step::Diarize, "modal_error", &message);
+            (JobStatus::Failed, Some(failure))
+        }
+        _ => return Ok(Json("Success".to_string())),
+    };
+
+    if let Some(job) = job {
+        let patch = JobPatch {
+            status: Some(job_status),
+            last_error: failure.as_ref().map(|failure| failure.message.clone()),
+            ..Default::default()
+        };
+        JobController::update(&db.surreal, &job.id.to_string(), &patch).await?;
+    }
+
+    if let Some(failure) = failure {
+        pipeline::fail_transcription(&db.surreal, &id, failure).await?;
     }
 
     Ok(Json("Success".to_string()))
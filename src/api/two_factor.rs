@@ -0,0 +1,141 @@
+use std::str::FromStr;
+
+use actix_web::{
+    HttpMessage, HttpRequest, post,
+    web::{Data, Json},
+};
+use serde::{Deserialize, Serialize};
+use surrealitos::SurrealId;
+
+use crate::{
+    api::auth::record_failure,
+    error::{Error, Result},
+    model::{
+        Controller,
+        throttle::{self, ThrottleController},
+        token::Claims,
+        two_factor::{TwoFactorController, TwoFactorSetup},
+        user::{PasswordHasher, User, UserController},
+    },
+    repo::surreal::SurrealDB,
+};
+
+#[derive(Serialize, Deserialize)]
+pub struct CodePayload {
+    code: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DisableTwoFactorPayload {
+    password: String,
+    code: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct RecoveryCodesResponse {
+    recovery_codes: Vec<String>,
+}
+
+async fn get_registered_user(db: &SurrealDB, req: &HttpRequest) -> Result<User> {
+    let claims = req.extensions().get::<Claims>().unwrap().clone();
+    let id = SurrealId::from_str(&claims.sub)?;
+    let user = UserController::get(&db.surreal, &id)
+        .await?
+        .ok_or(Error::WrongCredentials)?;
+
+    // Guests have no password for a second factor to protect
+    if user.password_hash.is_none() {
+        return Err(Error::BadRequest(
+            "Two-factor authentication needs a registered account".to_string(),
+        ));
+    }
+
+    Ok(user)
+}
+
+// Codes are short, so wrong ones count against the user whichever endpoint they come through
+pub async fn throttled_two_factor<T>(
+    db: &SurrealDB,
+    user: &User,
+    attempt: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let keys = [throttle::key("2fa", "user", &user.id.to_string())];
+    ThrottleController::check(&db.surreal, &keys).await?;
+
+    match attempt.await {
+        Ok(value) => {
+            ThrottleController::clear(&db.surreal, &keys[0]).await?;
+            Ok(value)
+        }
+        Err(error) => Err(record_failure(db, &keys, &throttle::TWO_FACTOR, error).await),
+    }
+}
+
+#[post("/2fa/setup")]
+pub async fn setup_two_factor(
+    db: Data<SurrealDB>,
+    req: HttpRequest,
+) -> Result<Json<TwoFactorSetup>> {
+    let user = get_registered_user(&db, &req).await?;
+    let setup = TwoFactorController::setup(&db.surreal, &user).await?;
+
+    Ok(Json(setup))
+}
+
+#[post("/2fa/confirm")]
+pub async fn confirm_two_factor(
+    db: Data<SurrealDB>,
+    body: Json<CodePayload>,
+    req: HttpRequest,
+) -> Result<Json<RecoveryCodesResponse>> {
+    let user = get_registered_user(&db, &req).await?;
+    let recovery_codes = throttled_two_factor(
+        &db,
+        &user,
+        TwoFactorController::confirm(&db.surreal, &user, &body.code),
+    )
+    .await?;
+
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
+
+#[post("/2fa/disable")]
+pub async fn disable_two_factor(
+    db: Data<SurrealDB>,
+    body: Json<DisableTwoFactorPayload>,
+    req: HttpRequest,
+) -> Result<Json<String>> {
+    let user = get_registered_user(&db, &req).await?;
+
+    throttled_two_factor(&db, &user, async {
+        let password_hasher = PasswordHasher::new();
+        password_hasher.verify(user.password_hash.as_ref().unwrap(), &body.password)?;
+        TwoFactorController::verify(&db.surreal, &user, &body.code).await
+    })
+    .await?;
+
+    TwoFactorController::delete_by_user(&db.surreal, &user.id.to_string()).await?;
+
+    Ok(Json(String::from("Success!")))
+}
+
+#[post("/2fa/recovery-codes")]
+pub async fn regenerate_recovery_codes(
+    db: Data<SurrealDB>,
+    body: Json<CodePayload>,
+    req: HttpRequest,
+) -> Result<Json<RecoveryCodesResponse>> {
+    let user = get_registered_user(&db, &req).await?;
+    throttled_two_factor(
+        &db,
+        &user,
+        TwoFactorController::verify(&db.surreal, &user, &body.code),
+    )
+    .await?;
+
+    let recovery_codes =
+        TwoFactorController::regenerate_recovery_codes(&db.surreal, &user.id.to_string()).await?;
+
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
@@ -4,19 +4,31 @@ use actix_web::{
     HttpMessage, HttpRequest, delete, get, put,
     web::{Data, Json},
 };
+use actix_web_httpauth::extractors::bearer::BearerAuth;
+use serde::{Deserialize, Serialize};
 use surrealitos::SurrealId;
 
 use crate::{
+    api::auth::record_failure,
     connector::backblaze::BackBlaze,
     error::{Error, Result},
     model::{
         Controller,
-        token::Claims,
-        user::{User, UserController, UserPatch},
+        throttle::{self, ThrottleController},
+        token::{Claims, TokenController},
+        user::{PasswordHasher, User, UserController, UserPatch},
     },
     repo::surreal::SurrealDB,
 };
 
+#[derive(Serialize, Deserialize)]
+pub struct ChangePasswordPayload {
+    #[serde(alias = "currentPassword")]
+    current_password: String,
+    #[serde(alias = "newPassword")]
+    new_password: String,
+}
+
 #[get("/me")]
 pub async fn get_user(db: Data<SurrealDB>, req: HttpRequest) -> Result<Json<User>> {
     let claims = req.extensions().get::<Claims>().unwrap().clone();
@@ -69,3 +81,57 @@ pub async fn delete_user(db: Data<SurrealDB>, req: HttpRequest) -> Result<Json<S
 
     Err(Error::WrongCredentials)
 }
+
+#[put("/password")]
+pub async fn change_password(
+    db: Data<SurrealDB>,
+    body: Json<ChangePasswordPayload>,
+    credentials: BearerAuth,
+    req: HttpRequest,
+) -> Result<Json<String>> {
+    let claims = req.extensions().get::<Claims>().unwrap().clone();
+    let id = SurrealId::from_str(&claims.sub)?;
+    let user = UserController::get(&db.surreal, &id)
+        .await?
+        .ok_or(Error::WrongCredentials)?;
+
+    // Resolved before anything changes, so a request without a session changes nothing
+    let current_token = TokenController::get_by_access_token(&db.surreal, credentials.token())
+        .await?
+        .ok_or(Error::TokenMismatch)?;
+
+    let password_hash = user
+        .password_hash
+        .as_ref()
+        .ok_or(Error::BadRequest("Account has no password".to_string()))?;
+
+    if body.new_password.is_empty() {
+        return Err(Error::BadRequest("newPassword is required".to_string()));
+    }
+
+    // Same lockout as login, otherwise this endpoint could be used to guess the password
+    let keys = [throttle::key("password", "user", &user.id.to_string())];
+    ThrottleController::check(&db.surreal, &keys).await?;
+
+    let password_hasher = PasswordHasher::new();
+    if let Err(error) = password_hasher.verify(password_hash, &body.current_password) {
+        return Err(record_failure(&db, &keys, &throttle::LOGIN, error).await);
+    }
+    ThrottleController::clear(&db.surreal, &keys[0]).await?;
+
+    let patch = UserPatch {
+        password_hash: Some(password_hasher.derive(&body.new_password)?),
+        ..Default::default()
+    };
+    UserController::update(&db.surreal, &user.id.to_string(), &patch).await?;
+
+    // Keep the device that made the change signed in
+    TokenController::delete_other_devices(
+        &db.surreal,
+        &user.id.to_string(),
+        &current_token.device_id,
+    )
+    .await?;
+
+    Ok(Json(String::from("Success!")))
+}
@@ -0,0 +1,416 @@
+use std::{env, fs, path::Path, sync::OnceLock};
+
+use serde::Deserialize;
+
+use crate::{
+    error::{Error, Result},
+    model::LLMProvider,
+    repo::surreal::is_embedded,
+};
+
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+static CONFIG: OnceLock<AppConfig> = OnceLock::new();
+
+// Loaded once in main from an optional TOML file, environment variables take precedence
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct AppConfig {
+    pub environment: String,
+    pub jwt_secret: String,
+    pub two_factor_secret: String, // Encrypts TOTP secrets, rotating it disables every authenticator
+    pub mistral_api_key: String,
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub storage: StorageConfig,
+    pub services: ServicesConfig,
+    pub llm: LlmConfig,
+    pub mail: MailConfig,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    pub public_url: String,      // Where Modal sends webhooks
+    pub app_url: Option<String>, // Where email links point, the public url when unset
+    // Header the proxy in front puts the client address in, like Fly-Client-IP
+    pub trusted_proxy_header: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct DatabaseConfig {
+    pub url: String, // wss://host for the remote database, mem:// for an in-memory one
+    pub username: String,
+    pub password: String,
+    pub namespace: String,
+    pub database: Option<String>, // prod or staging depending on the environment when unset
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    pub bucket: String,
+    pub region: String,
+    pub endpoint: String,
+    pub public_url: String,
+    pub read_key_id: String,
+    pub read_secret_key: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ServicesConfig {
+    pub modal_url: String,
+    pub reverb_url: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct LlmConfig {
+    pub provider: String, // Used when neither the request nor the user picked one
+    pub model: Option<String>, // The provider's own default when unset
+    pub openai_api_key: String,
+    pub anthropic_api_key: String,
+    pub google_api_key: String,
+    pub ollama_host: String,
+    pub mock: bool, // Canned answers instead of provider calls, no key needed
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct MailConfig {
+    pub transport: String, // smtp sends through smtp_host, log only outside deployments
+    pub smtp_host: String,
+    pub smtp_username: String,
+    pub smtp_password: String,
+    pub from: String,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        AppConfig {
+            environment: String::from("development"),
+            jwt_secret: String::new(),
+            two_factor_secret: String::new(),
+            mistral_api_key: String::new(),
+            server: ServerConfig::default(),
+            database: DatabaseConfig {
+                namespace: String::from("echo"),
+                ..Default::default()
+            },
+            storage: StorageConfig::default(),
+            services: ServicesConfig::default(),
+            llm: LlmConfig::default(),
+            mail: MailConfig::default(),
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            host: String::from("0.0.0.0"),
+            port: 8080,
+            public_url: String::new(),
+            app_url: None,
+            trusted_proxy_header: None,
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            bucket: String::new(),
+            region: String::from("us-west-004"),
+            endpoint: String::from("s3.us-west-004.backblazeb2.com"),
+            public_url: String::from("https://f004.backblazeb2.com"),
+            read_key_id: String::new(),
+            read_secret_key: String::new(),
+        }
+    }
+}
+
+impl Default for ServicesConfig {
+    fn default() -> Self {
+        ServicesConfig {
+            modal_url: String::new(),
+            reverb_url: String::from("http://localhost:4000"),
+        }
+    }
+}
+
+impl Default for LlmConfig {
+    fn default() -> Self {
+        LlmConfig {
+            provider: String::from("openai"),
+            model: None,
+            openai_api_key: String::new(),
+            anthropic_api_key: String::new(),
+            google_api_key: String::new(),
+            ollama_host: String::from("http://localhost:11434"),
+            mock: false,
+        }
+    }
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        MailConfig {
+            transport: String::from("log"),
+            smtp_host: String::new(),
+            smtp_username: String::new(),
+            smtp_password: String::new(),
+            from: String::from("Echo <no-reply@echo.app>"),
+        }
+    }
+}
+
+impl LlmConfig {
+    pub fn default_provider(&self) -> Result<LLMProvider> {
+        self.provider.parse().map_err(|_| {
+            Error::Config(format!(
+                "LLM_PROVIDER has to be openai, anthropic, google or ollama, got {}",
+                self.provider
+            ))
+        })
+    }
+
+    // Ollama runs next to the server and has no key
+    pub fn api_key(&self, provider: &LLMProvider) -> Result<&str> {
+        let (key, value) = match provider {
+            LLMProvider::OpenAI => ("OPENAI_API_KEY", &self.openai_api_key),
+            LLMProvider::Anthropic => ("ANTHROPIC_API_KEY", &self.anthropic_api_key),
+            LLMProvider::Google => ("GOOGLE_API_KEY", &self.google_api_key),
+            LLMProvider::Ollama => return Ok(""),
+        };
+
+        if value.trim().is_empty() {
+            return Err(Error::Config(format!("{key} is not set")));
+        }
+
+        Ok(value)
+    }
+}
+
+fn override_from_env(field: &mut String, key: &str) {
+    if let Ok(value) = env::var(key) {
+        *field = value;
+    }
+}
+
+fn override_optional_from_env(field: &mut Option<String>, key: &str) {
+    if let Ok(value) = env::var(key) {
+        *field = Some(value);
+    }
+}
+
+impl AppConfig {
+    pub fn load() -> Result<Self> {
+        let path = env::var("CONFIG_FILE").ok();
+        let mut config = match &path {
+            Some(path) => Self::from_file(Path::new(path))?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => AppConfig::default(),
+        };
+
+        config.apply_env()?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .map_err(|error| Error::Config(format!("Reading {}: {error}", path.display())))?;
+
+        toml::from_str(&contents)
+            .map_err(|error| Error::Config(format!("Parsing {}: {error}", path.display())))
+    }
+
+    fn apply_env(&mut self) -> Result<()> {
+        override_from_env(&mut self.environment, "PROJECT_ENV");
+        override_from_env(&mut self.jwt_secret, "JWT_SECRET");
+        override_from_env(&mut self.two_factor_secret, "TWO_FACTOR_SECRET");
+        override_from_env(&mut self.mistral_api_key, "MISTRAL_API_KEY");
+
+        override_from_env(&mut self.server.host, "HOST");
+        override_from_env(&mut self.server.public_url, "PUBLIC_URL");
+        override_optional_from_env(&mut self.server.app_url, "APP_URL");
+        override_optional_from_env(
+            &mut self.server.trusted_proxy_header,
+            "TRUSTED_PROXY_HEADER",
+        );
+        if let Ok(port) = env::var("PORT") {
+            self.server.port = port
+                .parse()
+                .map_err(|_| Error::Config(format!("PORT has to be a number, got {port}")))?;
+        }
+
+        override_from_env(&mut self.database.url, "SURREAL_URL");
+        override_from_env(&mut self.database.username, "SURREAL_USER");
+        override_from_env(&mut self.database.password, "SURREAL_PASS");
+        override_from_env(&mut self.database.namespace, "SURREAL_NS");
+        override_optional_from_env(&mut self.database.database, "SURREAL_DB");
+
+        override_from_env(&mut self.storage.bucket, "BUCKET");
+        override_from_env(&mut self.storage.region, "STORAGE_REGION");
+        override_from_env(&mut self.storage.endpoint, "STORAGE_ENDPOINT");
+        override_from_env(&mut self.storage.public_url, "STORAGE_PUBLIC_URL");
+        override_from_env(&mut self.storage.read_key_id, "B2_READ_ACCESS_KEY");
+        override_from_env(&mut self.storage.read_secret_key, "B2_READ_SECRET_KEY");
+
+        override_from_env(&mut self.services.modal_url, "MODAL_URL");
+        override_from_env(&mut self.services.reverb_url, "REVERB_URL");
+
+        override_from_env(&mut self.llm.provider, "LLM_PROVIDER");
+        override_optional_from_env(&mut self.llm.model, "LLM_MODEL");
+        override_from_env(&mut self.llm.openai_api_key, "OPENAI_API_KEY");
+        override_from_env(&mut self.llm.anthropic_api_key, "ANTHROPIC_API_KEY");
+        override_from_env(&mut self.llm.google_api_key, "GOOGLE_API_KEY");
+        override_from_env(&mut self.llm.ollama_host, "OLLAMA_HOST");
+        if let Ok(mock) = env::var("LLM_MOCK") {
+            self.llm.mock = mock == "true" || mock == "1";
+        }
+
+        override_from_env(&mut self.mail.transport, "MAIL_TRANSPORT");
+        override_from_env(&mut self.mail.smtp_host, "SMTP_HOST");
+        override_from_env(&mut self.mail.smtp_username, "SMTP_USERNAME");
+        override_from_env(&mut self.mail.smtp_password, "SMTP_PASSWORD");
+        override_from_env(&mut self.mail.from, "MAIL_FROM");
+
+        Ok(())
+    }
+
+    // Reports every missing setting at once instead of failing on the first
+    fn validate(&self) -> Result<()> {
+        let mut required = vec![
+            ("JWT_SECRET", &self.jwt_secret),
+            ("TWO_FACTOR_SECRET", &self.two_factor_secret),
+            ("MISTRAL_API_KEY", &self.mistral_api_key),
+            ("PUBLIC_URL", &self.server.public_url),
+            ("SURREAL_URL", &self.database.url),
+            ("SURREAL_NS", &self.database.namespace),
+            ("BUCKET", &self.storage.bucket),
+            ("STORAGE_REGION", &self.storage.region),
+            ("STORAGE_ENDPOINT", &self.storage.endpoint),
+            ("STORAGE_PUBLIC_URL", &self.storage.public_url),
+            ("B2_READ_ACCESS_KEY", &self.storage.read_key_id),
+            ("B2_READ_SECRET_KEY", &self.storage.read_secret_key),
+            ("MODAL_URL", &self.services.modal_url),
+            ("REVERB_URL", &self.services.reverb_url),
+        ];
+
+        if !is_embedded(&self.database.url) {
+            required.push(("SURREAL_USER", &self.database.username));
+            required.push(("SURREAL_PASS", &self.database.password));
+        }
+
+        if self.mail.transport == "smtp" {
+            required.push(("SMTP_HOST", &self.mail.smtp_host));
+        }
+
+        let missing: Vec<&str> = required
+            .iter()
+            .filter(|(_, value)| value.trim().is_empty())
+            .map(|(key, _)| *key)
+            .collect();
+
+        if !missing.is_empty() {
+            let missing = missing.join(", ");
+            return Err(Error::Config(format!(
+                "Missing required settings: {missing}"
+            )));
+        }
+
+        for (key, url) in [
+            ("PUBLIC_URL", &self.server.public_url),
+            ("STORAGE_PUBLIC_URL", &self.storage.public_url),
+            ("MODAL_URL", &self.services.modal_url),
+            ("REVERB_URL", &self.services.reverb_url),
+        ] {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(Error::Config(format!(
+                    "{key} has to be an http(s) url, got {url}"
+                )));
+            }
+        }
+
+        // A deploy that forgot MAIL_TRANSPORT would otherwise drop every email silently
+        if self.mail.transport != "smtp" && !self.is_local() {
+            let transport = &self.mail.transport;
+            return Err(Error::Config(format!(
+                "MAIL_TRANSPORT has to be smtp outside development, got {transport}"
+            )));
+        }
+
+        if !self.database.url.contains("://") {
+            let url = &self.database.url;
+            return Err(Error::Config(format!(
+                "SURREAL_URL needs a scheme like wss:// or mem://, got {url}"
+            )));
+        }
+
+        // Other providers can be picked per request and fail there when their key is missing
+        let provider = self.llm.default_provider()?;
+        if !self.llm.mock {
+            self.llm.api_key(&provider)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn is_production(&self) -> bool {
+        self.environment == "prod"
+    }
+
+    fn is_local(&self) -> bool {
+        matches!(self.environment.as_str(), "development" | "test")
+    }
+
+    pub fn app_url(&self) -> &str {
+        self.server
+            .app_url
+            .as_deref()
+            .unwrap_or(&self.server.public_url)
+    }
+
+    pub fn database_name(&self) -> &str {
+        match &self.database.database {
+            Some(database) => database.as_str(),
+            None if self.is_production() => "prod",
+            None => "staging",
+        }
+    }
+}
+
+// The one copy of the config, read through get() by handlers, connectors and the worker
+// alike since most of them run outside of a request and have no app data to read
+pub fn init(config: AppConfig) {
+    CONFIG
+        .set(config)
+        .expect("AppConfig is only initialized once");
+}
+
+pub fn get() -> &'static AppConfig {
+    CONFIG.get().expect("AppConfig is loaded in main")
+}
+
+// Tests share the process, whichever runs first sets up the config for all of them
+#[cfg(test)]
+pub fn init_for_tests() -> &'static AppConfig {
+    CONFIG.get_or_init(|| AppConfig {
+        jwt_secret: String::from("test-jwt-secret"),
+        two_factor_secret: String::from("test-two-factor-secret"),
+        server: ServerConfig {
+            public_url: String::from("http://localhost:8080"),
+            ..Default::default()
+        },
+        ..Default::default()
+    })
+}
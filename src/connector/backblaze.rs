@@ -1,9 +1,12 @@
-use std::env;
-
 use reqwest::Client;
+use rusoto_core::Region;
+use rusoto_s3::{DeleteObjectRequest, S3, S3Client};
 use serde::{Deserialize, Serialize};
 
-use crate::error::Result;
+use crate::{
+    config,
+    error::{Error, Result},
+};
 
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all(deserialize = "camelCase", serialize = "snake_case"))]
@@ -20,17 +23,26 @@ pub struct AuthorizationResponse {
 pub struct BackBlaze;
 
 impl BackBlaze {
+    // S3 compatible endpoint of the bucket
+    pub fn region() -> Region {
+        let storage = &config::get().storage;
+
+        Region::Custom {
+            name: storage.region.clone(),
+            endpoint: storage.endpoint.clone(),
+        }
+    }
+
     pub async fn get_read_auth_token() -> Result<String> {
         let client = Client::new();
         let authorize_url =
             String::from("https://api.backblazeb2.com/b2api/v2/b2_authorize_account");
 
-        let key_id = env::var("B2_READ_ACCESS_KEY").unwrap();
-        let key = env::var("B2_READ_SECRET_KEY").unwrap();
+        let storage = &config::get().storage;
 
         let auth_response = client
             .get(authorize_url)
-            .basic_auth(key_id, Some(key))
+            .basic_auth(&storage.read_key_id, Some(&storage.read_secret_key))
             .send()
             .await?
             .json::<AuthorizationResponse>()
@@ -38,4 +50,60 @@ impl BackBlaze {
 
         Ok(auth_response.authorization_token)
     }
+
+    // Appends a fresh read token so external services can download a private file
+    pub async fn authorize_url(file_url: &str) -> Result<String> {
+        if file_url.contains("Authorization=") {
+            return Ok(file_url.to_string());
+        }
+
+        let read_blaze_token = Self::get_read_auth_token().await?;
+        let separator = if file_url.contains('?') { "&" } else { "?" };
+
+        Ok(format!(
+            "{}{}Authorization={}",
+            file_url, separator, read_blaze_token
+        ))
+    }
+
+    pub fn sanitize_url(file_url: &str) -> String {
+        match file_url.find('?') {
+            Some(question_mark_pos) => file_url[..question_mark_pos].to_string(),
+            None => file_url.to_string(),
+        }
+    }
+
+    // Public url of an object in the bucket
+    pub fn file_url(key: &str) -> String {
+        let storage = &config::get().storage;
+        format!("{}/file/{}/{key}", storage.public_url, storage.bucket)
+    }
+
+    // Removes the object behind a public file url like {public_url}/file/{bucket}/{key}.
+    // Urls come from clients, ones outside the bucket have nothing of ours to remove
+    pub async fn delete_file(file_url: &str) -> Result<()> {
+        let bucket = config::get().storage.bucket.clone();
+        let file_prefix = format!("/file/{bucket}/");
+        let url = Self::sanitize_url(file_url);
+
+        let Some((_, key)) = url.split_once(&file_prefix) else {
+            log::warn!("Skipping delete of {url}, it is not stored in {bucket}");
+            return Ok(());
+        };
+        let key = key.to_string();
+
+        let client = S3Client::new(Self::region());
+        let request = DeleteObjectRequest {
+            bucket,
+            key,
+            ..Default::default()
+        };
+
+        client
+            .delete_object(request)
+            .await
+            .map_err(|error| Error::Storage(error.to_string()))?;
+
+        Ok(())
+    }
 }
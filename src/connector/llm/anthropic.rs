@@ -0,0 +1,167 @@
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::{
+    config,
+    connector::llm::{CompletionRequest, LlmConnector, Message, OutputSchema},
+    error::{Error, Result},
+    model::LLMProvider,
+};
+
+const DEFAULT_MODEL: &str = "claude-3-5-haiku-latest";
+const API_VERSION: &str = "2023-06-01";
+// Anthropic requires an explicit output limit on every request
+const DEFAULT_MAX_TOKENS: usize = 4096;
+
+#[derive(Serialize)]
+struct MessagesRequest<'a> {
+    model: &'a str,
+    max_tokens: usize,
+    messages: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ContentBlock {
+    Text {
+        text: String,
+    },
+    ToolUse {
+        input: Value,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct MessagesResponse {
+    content: Vec<ContentBlock>,
+}
+
+pub struct Anthropic {
+    client: Client,
+    base_url: String,
+    api_key: String,
+    model: String,
+}
+
+impl Anthropic {
+    pub fn new(model: Option<String>) -> Result<Self> {
+        let api_key = config::get()
+            .llm
+            .api_key(&LLMProvider::Anthropic)?
+            .to_string();
+
+        Ok(Anthropic {
+            client: Client::new(),
+            base_url: "https://api.anthropic.com/v1".to_string(),
+            api_key,
+            model: model.unwrap_or(DEFAULT_MODEL.to_string()),
+        })
+    }
+
+    async fn messages(
+        &self,
+        request: &CompletionRequest,
+        tools: Option<Value>,
+        tool_choice: Option<Value>,
+    ) -> Result<MessagesResponse> {
+        let url = format!("{}/messages", self.base_url);
+        let (system, messages) = request.split_system();
+
+        let body = MessagesRequest {
+            model: &self.model,
+            max_tokens: request.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            messages,
+            system,
+            temperature: request.temperature,
+            tools,
+            tool_choice,
+        };
+
+        let response = self
+            .client
+            .post(url)
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", API_VERSION)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<MessagesResponse>()
+            .await?;
+
+        Ok(response)
+    }
+}
+
+#[async_trait]
+impl LlmConnector for Anthropic {
+    fn provider(&self) -> LLMProvider {
+        LLMProvider::Anthropic
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    async fn complete(&self, request: &CompletionRequest) -> Result<String> {
+        let response = self.messages(request, None, None).await?;
+
+        let text: Vec<String> = response
+            .content
+            .into_iter()
+            .filter_map(|block| match block {
+                ContentBlock::Text { text } => Some(text),
+                _ => None,
+            })
+            .collect();
+
+        if text.is_empty() {
+            return Err(Error::Llm(
+                "Anthropic returned an empty completion".to_string(),
+            ));
+        }
+
+        Ok(text.join(""))
+    }
+
+    async fn complete_structured(
+        &self,
+        request: &CompletionRequest,
+        output: &OutputSchema,
+    ) -> Result<Value> {
+        // Forcing a single tool call is how Anthropic guarantees schema shaped output
+        let tools = json!([{
+            "name": output.name,
+            "description": output.description,
+            "input_schema": output.schema,
+        }]);
+        let tool_choice = json!({ "type": "tool", "name": output.name });
+
+        let response = self
+            .messages(request, Some(tools), Some(tool_choice))
+            .await?;
+
+        response
+            .content
+            .into_iter()
+            .find_map(|block| match block {
+                ContentBlock::ToolUse { input } => Some(input),
+                _ => None,
+            })
+            .ok_or(Error::Llm(
+                "Anthropic did not return structured output".to_string(),
+            ))
+    }
+}
@@ -0,0 +1,174 @@
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::{
+    config,
+    connector::llm::{CompletionRequest, LlmConnector, OutputSchema, Role},
+    error::{Error, Result},
+    model::LLMProvider,
+};
+
+const DEFAULT_MODEL: &str = "gemini-2.0-flash";
+
+#[derive(Serialize, Deserialize)]
+struct Part {
+    text: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Content {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    role: Option<String>,
+    #[serde(default)]
+    parts: Vec<Part>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GenerateRequest {
+    contents: Vec<Content>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system_instruction: Option<Content>,
+    generation_config: Value,
+}
+
+#[derive(Deserialize)]
+struct Candidate {
+    content: Option<Content>,
+}
+
+#[derive(Deserialize)]
+struct GenerateResponse {
+    #[serde(default)]
+    candidates: Vec<Candidate>,
+}
+
+// Gemini only understands an OpenAPI subset of JSON schema
+fn strip_unsupported(schema: &Value) -> Value {
+    match schema {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .filter(|(key, _)| key.as_str() != "additionalProperties")
+                .map(|(key, value)| (key.clone(), strip_unsupported(value)))
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.iter().map(strip_unsupported).collect()),
+        other => other.clone(),
+    }
+}
+
+pub struct Google {
+    client: Client,
+    base_url: String,
+    api_key: String,
+    model: String,
+}
+
+impl Google {
+    pub fn new(model: Option<String>) -> Result<Self> {
+        let api_key = config::get().llm.api_key(&LLMProvider::Google)?.to_string();
+
+        Ok(Google {
+            client: Client::new(),
+            base_url: "https://generativelanguage.googleapis.com/v1beta".to_string(),
+            api_key,
+            model: model.unwrap_or(DEFAULT_MODEL.to_string()),
+        })
+    }
+
+    async fn generate(&self, request: &CompletionRequest, schema: Option<Value>) -> Result<String> {
+        let url = format!("{}/models/{}:generateContent", self.base_url, self.model);
+        let (system, messages) = request.split_system();
+
+        let contents = messages
+            .into_iter()
+            .map(|message| Content {
+                role: Some(match message.role {
+                    Role::Assistant => "model".to_string(),
+                    _ => "user".to_string(),
+                }),
+                parts: vec![Part {
+                    text: message.content,
+                }],
+            })
+            .collect();
+
+        let mut generation_config = json!({});
+        if let Some(temperature) = request.temperature {
+            generation_config["temperature"] = json!(temperature);
+        }
+        if let Some(max_tokens) = request.max_tokens {
+            generation_config["maxOutputTokens"] = json!(max_tokens);
+        }
+        if request.json || schema.is_some() {
+            generation_config["responseMimeType"] = json!("application/json");
+        }
+        if let Some(schema) = schema {
+            generation_config["responseSchema"] = schema;
+        }
+
+        let body = GenerateRequest {
+            contents,
+            system_instruction: system.map(|text| Content {
+                role: None,
+                parts: vec![Part { text }],
+            }),
+            generation_config,
+        };
+
+        let response = self
+            .client
+            .post(url)
+            .header("x-goog-api-key", &self.api_key)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<GenerateResponse>()
+            .await?;
+
+        let text: Vec<String> = response
+            .candidates
+            .into_iter()
+            .next()
+            .and_then(|candidate| candidate.content)
+            .map(|content| content.parts.into_iter().map(|part| part.text).collect())
+            .unwrap_or_default();
+
+        if text.is_empty() {
+            return Err(Error::Llm(
+                "Google returned an empty completion".to_string(),
+            ));
+        }
+
+        Ok(text.join(""))
+    }
+}
+
+#[async_trait]
+impl LlmConnector for Google {
+    fn provider(&self) -> LLMProvider {
+        LLMProvider::Google
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    async fn complete(&self, request: &CompletionRequest) -> Result<String> {
+        self.generate(request, None).await
+    }
+
+    async fn complete_structured(
+        &self,
+        request: &CompletionRequest,
+        output: &OutputSchema,
+    ) -> Result<Value> {
+        let content = self
+            .generate(request, Some(strip_unsupported(&output.schema)))
+            .await?;
+        Ok(serde_json::from_str(&content)?)
+    }
+}
@@ -0,0 +1,83 @@
+use async_trait::async_trait;
+use serde_json::{Map, Value};
+
+use crate::{
+    connector::llm::{CompletionRequest, LlmConnector, OutputSchema, Role},
+    error::Result,
+    model::LLMProvider,
+};
+
+// Offline stand in for any provider, enabled with LLM_MOCK=true
+pub struct MockLlm {
+    provider: LLMProvider,
+    model: String,
+}
+
+impl MockLlm {
+    pub fn new(provider: LLMProvider, model: Option<String>) -> Self {
+        MockLlm {
+            provider,
+            model: model.unwrap_or(String::from("mock")),
+        }
+    }
+}
+
+// Builds the smallest value that satisfies the given JSON schema
+fn mock_value(schema: &Value) -> Value {
+    match schema.get("type").and_then(Value::as_str) {
+        Some("object") => {
+            let properties = schema
+                .get("properties")
+                .and_then(Value::as_object)
+                .map(|properties| {
+                    properties
+                        .iter()
+                        .map(|(key, value)| (key.clone(), mock_value(value)))
+                        .collect::<Map<String, Value>>()
+                })
+                .unwrap_or_default();
+
+            Value::Object(properties)
+        }
+        Some("array") => Value::Array(vec![]),
+        Some("number") | Some("integer") => Value::from(0),
+        Some("boolean") => Value::Bool(false),
+        Some("string") => Value::String(String::from("mock")),
+        _ => Value::Null,
+    }
+}
+
+#[async_trait]
+impl LlmConnector for MockLlm {
+    fn provider(&self) -> LLMProvider {
+        self.provider.clone()
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    async fn complete(&self, request: &CompletionRequest) -> Result<String> {
+        if request.json {
+            return Ok(String::from("{}"));
+        }
+
+        let last_user_message = request
+            .messages
+            .iter()
+            .rev()
+            .find(|message| message.role == Role::User)
+            .map(|message| message.content.clone())
+            .unwrap_or_default();
+
+        Ok(last_user_message)
+    }
+
+    async fn complete_structured(
+        &self,
+        _request: &CompletionRequest,
+        output: &OutputSchema,
+    ) -> Result<Value> {
+        Ok(mock_value(&output.schema))
+    }
+}
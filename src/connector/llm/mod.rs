@@ -0,0 +1,156 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+
+use crate::{
+    config,
+    connector::llm::{
+        anthropic::Anthropic, google::Google, mock::MockLlm, ollama::Ollama, openai::OpenAI,
+    },
+    error::Result,
+    model::LLMProvider,
+};
+
+pub mod anthropic;
+pub mod google;
+pub mod mock;
+pub mod ollama;
+pub mod openai;
+pub mod summary;
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Message {
+    pub role: Role,
+    pub content: String,
+}
+
+impl Message {
+    pub fn system(content: &str) -> Self {
+        Message {
+            role: Role::System,
+            content: content.to_string(),
+        }
+    }
+
+    pub fn user(content: &str) -> Self {
+        Message {
+            role: Role::User,
+            content: content.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CompletionRequest {
+    pub messages: Vec<Message>,
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<usize>,
+    // Ask the provider to answer with a single JSON object
+    pub json: bool,
+}
+
+impl CompletionRequest {
+    // Providers like Anthropic and Google take the system prompt apart from the conversation
+    pub fn split_system(&self) -> (Option<String>, Vec<Message>) {
+        let system: Vec<&str> = self
+            .messages
+            .iter()
+            .filter(|message| message.role == Role::System)
+            .map(|message| message.content.as_str())
+            .collect();
+
+        let messages = self
+            .messages
+            .iter()
+            .filter(|message| message.role != Role::System)
+            .cloned()
+            .collect();
+
+        let system = if system.is_empty() {
+            None
+        } else {
+            Some(system.join("\n\n"))
+        };
+
+        (system, messages)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct OutputSchema {
+    pub name: String,
+    pub description: String,
+    // JSON schema of the expected object
+    pub schema: Value,
+}
+
+#[async_trait]
+pub trait LlmConnector: Send + Sync {
+    fn provider(&self) -> LLMProvider;
+
+    fn model(&self) -> &str;
+
+    async fn complete(&self, request: &CompletionRequest) -> Result<String>;
+
+    async fn complete_structured(
+        &self,
+        request: &CompletionRequest,
+        output: &OutputSchema,
+    ) -> Result<Value>;
+}
+
+pub async fn structured<O>(
+    llm: &dyn LlmConnector,
+    request: &CompletionRequest,
+    output: &OutputSchema,
+) -> Result<O>
+where
+    O: DeserializeOwned,
+{
+    let value = llm.complete_structured(request, output).await?;
+    Ok(serde_json::from_value(value)?)
+}
+
+// Fails instead of panicking the worker when the provider has no key configured
+pub fn connector_for(
+    provider: &LLMProvider,
+    model: Option<String>,
+) -> Result<Box<dyn LlmConnector>> {
+    if config::get().llm.mock {
+        return Ok(Box::new(MockLlm::new(provider.clone(), model)));
+    }
+
+    let connector: Box<dyn LlmConnector> = match provider {
+        LLMProvider::OpenAI => Box::new(OpenAI::new(model)?),
+        LLMProvider::Anthropic => Box::new(Anthropic::new(model)?),
+        LLMProvider::Google => Box::new(Google::new(model)?),
+        LLMProvider::Ollama => Box::new(Ollama::new(model)),
+    };
+
+    Ok(connector)
+}
+
+// Picks the connector for a request, falling back to the server wide defaults
+pub fn select(
+    provider: Option<LLMProvider>,
+    model: Option<String>,
+) -> Result<Box<dyn LlmConnector>> {
+    match provider {
+        Some(provider) => connector_for(&provider, model),
+        None => {
+            let llm = &config::get().llm;
+            let provider = llm.default_provider()?;
+            let model = model.or(llm.model.clone());
+
+            connector_for(&provider, model)
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::{
+    config,
+    connector::llm::{CompletionRequest, LlmConnector, Message, OutputSchema},
+    error::{Error, Result},
+    model::LLMProvider,
+};
+
+const DEFAULT_MODEL: &str = "llama3.1";
+
+#[derive(Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: &'a [Message],
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<Value>,
+    options: Value,
+}
+
+#[derive(Deserialize)]
+struct ChatMessage {
+    content: String,
+}
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    message: Option<ChatMessage>,
+}
+
+pub struct Ollama {
+    client: Client,
+    base_url: String,
+    model: String,
+}
+
+impl Ollama {
+    pub fn new(model: Option<String>) -> Self {
+        let base_url = config::get().llm.ollama_host.clone();
+
+        Ollama {
+            client: Client::new(),
+            base_url,
+            model: model.unwrap_or(DEFAULT_MODEL.to_string()),
+        }
+    }
+
+    async fn chat(&self, request: &CompletionRequest, format: Option<Value>) -> Result<String> {
+        let url = format!("{}/api/chat", self.base_url);
+
+        let mut options = json!({});
+        if let Some(temperature) = request.temperature {
+            options["temperature"] = json!(temperature);
+        }
+        if let Some(max_tokens) = request.max_tokens {
+            options["num_predict"] = json!(max_tokens);
+        }
+
+        let body = ChatRequest {
+            model: &self.model,
+            messages: &request.messages,
+            stream: false,
+            format,
+            options,
+        };
+
+        let response = self
+            .client
+            .post(url)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<ChatResponse>()
+            .await?;
+
+        response
+            .message
+            .map(|message| message.content)
+            .filter(|content| !content.is_empty())
+            .ok_or(Error::Llm(
+                "Ollama returned an empty completion".to_string(),
+            ))
+    }
+}
+
+#[async_trait]
+impl LlmConnector for Ollama {
+    fn provider(&self) -> LLMProvider {
+        LLMProvider::Ollama
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    async fn complete(&self, request: &CompletionRequest) -> Result<String> {
+        let format = request.json.then(|| json!("json"));
+        self.chat(request, format).await
+    }
+
+    async fn complete_structured(
+        &self,
+        request: &CompletionRequest,
+        output: &OutputSchema,
+    ) -> Result<Value> {
+        let content = self.chat(request, Some(output.schema.clone())).await?;
+        Ok(serde_json::from_str(&content)?)
+    }
+}
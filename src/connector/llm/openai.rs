@@ -0,0 +1,131 @@
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::{
+    config,
+    connector::llm::{CompletionRequest, LlmConnector, Message, OutputSchema},
+    error::{Error, Result},
+    model::LLMProvider,
+};
+
+const DEFAULT_MODEL: &str = "gpt-4o-mini";
+
+#[derive(Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: &'a [Message],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct ChoiceMessage {
+    content: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    message: ChoiceMessage,
+}
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    choices: Vec<Choice>,
+}
+
+pub struct OpenAI {
+    client: Client,
+    base_url: String,
+    api_key: String,
+    model: String,
+}
+
+impl OpenAI {
+    pub fn new(model: Option<String>) -> Result<Self> {
+        let api_key = config::get().llm.api_key(&LLMProvider::OpenAI)?.to_string();
+
+        Ok(OpenAI {
+            client: Client::new(),
+            base_url: "https://api.openai.com/v1".to_string(),
+            api_key,
+            model: model.unwrap_or(DEFAULT_MODEL.to_string()),
+        })
+    }
+
+    async fn chat(
+        &self,
+        request: &CompletionRequest,
+        response_format: Option<Value>,
+    ) -> Result<String> {
+        let url = format!("{}/chat/completions", self.base_url);
+
+        let body = ChatRequest {
+            model: &self.model,
+            messages: &request.messages,
+            temperature: request.temperature,
+            max_tokens: request.max_tokens,
+            response_format,
+        };
+
+        let response = self
+            .client
+            .post(url)
+            .bearer_auth(&self.api_key)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<ChatResponse>()
+            .await?;
+
+        response
+            .choices
+            .into_iter()
+            .next()
+            .and_then(|choice| choice.message.content)
+            .ok_or(Error::Llm(
+                "OpenAI returned an empty completion".to_string(),
+            ))
+    }
+}
+
+#[async_trait]
+impl LlmConnector for OpenAI {
+    fn provider(&self) -> LLMProvider {
+        LLMProvider::OpenAI
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    async fn complete(&self, request: &CompletionRequest) -> Result<String> {
+        let response_format = request.json.then(|| json!({ "type": "json_object" }));
+        self.chat(request, response_format).await
+    }
+
+    async fn complete_structured(
+        &self,
+        request: &CompletionRequest,
+        output: &OutputSchema,
+    ) -> Result<Value> {
+        let response_format = json!({
+            "type": "json_schema",
+            "json_schema": {
+                "name": output.name,
+                "description": output.description,
+                "schema": output.schema,
+                "strict": true,
+            }
+        });
+
+        let content = self.chat(request, Some(response_format)).await?;
+        Ok(serde_json::from_str(&content)?)
+    }
+}
@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    connector::llm::{CompletionRequest, LlmConnector, Message, OutputSchema, structured},
+    error::Result,
+    model::transcription::Segment,
+};
+
+const SUMMARY_PROMPT: &str = "You turn diarized audio transcripts into concise notes. \
+Give the note a short title, summarize the conversation in a short paragraph, list its key points \
+and any action items that were agreed on (leave them empty if there are none). \
+Write the note in the same language as the transcript.";
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Note {
+    pub title: String,
+    pub summary: String,
+    #[serde(default)]
+    pub key_points: Vec<String>,
+    #[serde(default)]
+    pub action_items: Vec<String>,
+}
+
+impl Note {
+    pub fn output_schema() -> OutputSchema {
+        OutputSchema {
+            name: "note".to_string(),
+            description: "Structured note summarizing a transcript".to_string(),
+            schema: json!({
+                "type": "object",
+                "properties": {
+                    "title": { "type": "string" },
+                    "summary": { "type": "string" },
+                    "key_points": { "type": "array", "items": { "type": "string" } },
+                    "action_items": { "type": "array", "items": { "type": "string" } },
+                },
+                "required": ["title", "summary", "key_points", "action_items"],
+                "additionalProperties": false,
+            }),
+        }
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut markdown = format!("# {}\n\n{}\n", self.title, self.summary);
+
+        if !self.key_points.is_empty() {
+            markdown.push_str("\n## Key points\n\n");
+            for point in &self.key_points {
+                markdown.push_str(&format!("- {point}\n"));
+            }
+        }
+
+        if !self.action_items.is_empty() {
+            markdown.push_str("\n## Action items\n\n");
+            for item in &self.action_items {
+                markdown.push_str(&format!("- [ ] {item}\n"));
+            }
+        }
+
+        markdown
+    }
+}
+
+fn format_timestamp(seconds: f64) -> String {
+    let total = seconds.max(0.0) as u64;
+    format!(
+        "{:02}:{:02}:{:02}",
+        total / 3600,
+        (total % 3600) / 60,
+        total % 60
+    )
+}
+
+fn format_transcript(segments: &[Segment]) -> String {
+    segments
+        .iter()
+        .map(|segment| {
+            let speaker = segment.speaker.as_deref().unwrap_or("Speaker");
+            format!(
+                "[{}] {}: {}",
+                format_timestamp(segment.start),
+                speaker,
+                segment.text.trim()
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+pub async fn summarize(llm: &dyn LlmConnector, segments: &[Segment]) -> Result<Note> {
+    let request = CompletionRequest {
+        messages: vec![
+            Message::system(SUMMARY_PROMPT),
+            Message::user(&format_transcript(segments)),
+        ],
+        temperature: Some(0.2),
+        ..Default::default()
+    };
+
+    structured::<Note>(llm, &request, &Note::output_schema()).await
+}
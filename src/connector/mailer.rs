@@ -0,0 +1,118 @@
+use std::sync::Arc;
+#[cfg(test)]
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use lettre::{
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor, message::Mailbox,
+    transport::smtp::authentication::Credentials,
+};
+
+use crate::{
+    config::MailConfig,
+    error::{Error, Result},
+};
+
+#[derive(Clone, Debug)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, mail: Mail) -> Result<()>;
+}
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(host: &str, username: &str, password: &str, from: &str) -> Result<Self> {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::relay(host)
+            .map_err(|error| Error::Mail(error.to_string()))?
+            .credentials(Credentials::new(username.to_string(), password.to_string()))
+            .build();
+
+        let from = from
+            .parse::<Mailbox>()
+            .map_err(|error| Error::Mail(error.to_string()))?;
+
+        Ok(SmtpMailer { transport, from })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, mail: Mail) -> Result<()> {
+        let to = mail
+            .to
+            .parse::<Mailbox>()
+            .map_err(|error| Error::BadRequest(error.to_string()))?;
+
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(mail.subject)
+            .body(mail.body)
+            .map_err(|error| Error::Mail(error.to_string()))?;
+
+        self.transport
+            .send(message)
+            .await
+            .map_err(|error| Error::Mail(error.to_string()))?;
+
+        Ok(())
+    }
+}
+
+// Drops mail for local development, bodies carry live tokens so only the envelope is logged
+pub struct LogMailer;
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, mail: Mail) -> Result<()> {
+        log::debug!("Not sending mail to {}: {}", mail.to, mail.subject);
+        Ok(())
+    }
+}
+
+// Keeps mail in memory so tests can read what was sent
+#[cfg(test)]
+#[derive(Default)]
+pub struct CaptureMailer {
+    sent: Mutex<Vec<Mail>>,
+}
+
+#[cfg(test)]
+impl CaptureMailer {
+    pub fn sent(&self) -> Vec<Mail> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl Mailer for CaptureMailer {
+    async fn send(&self, mail: Mail) -> Result<()> {
+        self.sent.lock().unwrap().push(mail);
+        Ok(())
+    }
+}
+
+// The smtp transport sends through smtp_host, anything else only logs, see AppConfig::validate
+pub fn from_config(config: &MailConfig) -> Result<Arc<dyn Mailer>> {
+    if config.transport != "smtp" {
+        return Ok(Arc::new(LogMailer));
+    }
+
+    Ok(Arc::new(SmtpMailer::new(
+        &config.smtp_host,
+        &config.smtp_username,
+        &config.smtp_password,
+        &config.from,
+    )?))
+}
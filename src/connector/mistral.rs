@@ -1,9 +1,7 @@
-use std::env;
-
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
-use crate::{error::Result, model::transcription::Segment};
+use crate::{config, error::Result, model::transcription::Segment};
 
 #[derive(Deserialize, Serialize)]
 pub struct Usage {
@@ -41,7 +39,7 @@ impl Mistral {
         let url = format!("{}/audio/transcriptions", self.base_url);
         let mut form = vec![("file_url", file_url), ("model", "voxtral-mini-2507")];
 
-        let mistral_key = env::var("MISTRAL_API_KEY").unwrap();
+        let mistral_key = &config::get().mistral_api_key;
 
         if segment {
             form.push(("timestamp_granularities", "segment"));
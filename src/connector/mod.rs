@@ -1,4 +1,6 @@
 pub mod backblaze;
+pub mod llm;
+pub mod mailer;
 pub mod mistral;
 pub mod modal;
 pub mod reverb;
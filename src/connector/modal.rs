@@ -1,10 +1,8 @@
-use std::env;
-
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use surrealitos::SurrealId;
 
-use crate::{connector::HttpMethod, error::Result, model::transcription::Segment};
+use crate::{config, connector::HttpMethod, error::Result, model::transcription::Segment};
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct BaseParameters {
@@ -56,15 +54,9 @@ pub struct ModalAI {
 
 impl ModalAI {
     pub fn new() -> Self {
-        let project_env = env::var("PROJECT_ENV").unwrap_or(String::from("development"));
-        let base_url = match project_env.as_str() {
-            "prod" => "https://litsdm--orestiad-main.modal.run".to_string(),
-            _ => "https://litsdm--orestiad-main-dev.modal.run".to_string(),
-        };
-
         ModalAI {
             client: Client::new(),
-            base_url,
+            base_url: config::get().services.modal_url.clone(),
         }
     }
 
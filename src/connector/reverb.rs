@@ -1,9 +1,7 @@
-use std::env;
-
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
-use crate::error::Result;
+use crate::{config, error::Result};
 
 #[derive(Serialize, Deserialize)]
 pub struct UpdatePayload<D> {
@@ -18,14 +16,9 @@ pub struct Reverb {
 
 impl Reverb {
     pub fn new() -> Self {
-        let project_env = env::var("PROJECT_ENV").unwrap_or(String::from("development"));
-        let base_url = match project_env.as_str() {
-            "prod" => "https://echo-reverb.fly.dev",
-            _ => "http://localhost:4000",
-        };
         Reverb {
             client: Client::new(),
-            base_url: base_url.to_string(),
+            base_url: config::get().services.reverb_url.clone(),
         }
     }
 
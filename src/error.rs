@@ -1,7 +1,13 @@
+use std::collections::BTreeMap;
+
 use actix_web::{
     self, HttpResponse,
-    http::{StatusCode, header::ContentType},
+    http::{StatusCode, header::RETRY_AFTER},
 };
+use serde::Serialize;
+
+// Shown instead of the real message for server errors, which only go to the logs
+const INTERNAL_MESSAGE: &str = "Something went wrong on our side, try again later";
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -17,15 +23,48 @@ pub enum Error {
     #[error("{0} not found")]
     NotFound(String),
 
-    #[error("Unauthorized: Email already in use")]
+    #[error("Email already in use")]
     EmailInUse,
 
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
     #[error("Bad Request: {0}")]
     BadRequest(String),
 
+    #[error("Bad Request: Some fields are invalid")]
+    InvalidFields(BTreeMap<String, String>), // Field name to what is wrong with it
+
+    #[error("Bad Request: Invalid body, {0}")]
+    InvalidBody(String), // The client sent JSON we can't read, ours is Deserialize
+
+    #[error("Payload too large, the limit is {0} bytes")]
+    PayloadTooLarge(usize),
+
     #[error("Error creating {0}")]
     StoreData(String),
 
+    #[error("Upstream service failed: {0}")]
+    Upstream(String),
+
+    #[error("Upstream service timed out: {0}")]
+    Timeout(String),
+
+    #[error("LLM error: {0}")]
+    Llm(String),
+
+    #[error("Storage error: {0}")]
+    Storage(String),
+
+    #[error("Mail error: {0}")]
+    Mail(String),
+
+    #[error("Configuration error: {0}")]
+    Config(String),
+
+    #[error("Too many attempts, try again in {0} seconds")]
+    RateLimited(i64),
+
     #[error(transparent)]
     PasswordHash(#[from] argon2::password_hash::Error),
 
@@ -38,37 +77,387 @@ pub enum Error {
     #[error[transparent]]
     Deserialize(#[from] serde_json::error::Error),
 
-    #[error(transparent)]
-    Reqwest(#[from] reqwest::Error),
-
     #[error(transparent)]
     ParseSurrealId(#[from] surrealitos::SurrealIdParseError),
 }
 
 impl actix_web::error::ResponseError for Error {
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code())
-            .insert_header(ContentType::html())
-            .body(self.to_string())
+        self.response(None)
     }
 
     fn status_code(&self) -> StatusCode {
         match *self {
-            Error::NotFound(_) => StatusCode::NOT_FOUND,
-            Error::StoreData(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            Error::SurrealDB(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            // Client errors, the message is shown as is
+            Error::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Error::InvalidFields(_) => StatusCode::BAD_REQUEST,
+            Error::InvalidBody(_) => StatusCode::BAD_REQUEST,
+            Error::ParseSurrealId(_) => StatusCode::BAD_REQUEST,
             Error::WrongCredentials => StatusCode::UNAUTHORIZED,
             Error::TokenMismatch => StatusCode::UNAUTHORIZED,
             Error::Unauthorized => StatusCode::UNAUTHORIZED,
-            Error::EmailInUse => StatusCode::UNAUTHORIZED,
             Error::Jwt(_) => StatusCode::UNAUTHORIZED,
+            Error::Forbidden(_) => StatusCode::FORBIDDEN,
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::EmailInUse => StatusCode::CONFLICT,
+            Error::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Error::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+
+            // Server errors, the message only goes to the logs
+            Error::StoreData(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::Mail(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::Config(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::SurrealDB(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Error::Deserialize(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            Error::BadRequest(_) => StatusCode::BAD_REQUEST,
             Error::PasswordHash(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            Error::Reqwest(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            Error::ParseSurrealId(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::Upstream(_) => StatusCode::BAD_GATEWAY,
+            Error::Llm(_) => StatusCode::BAD_GATEWAY,
+            Error::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+}
+
+// Requests to other services fail on their side, or take too long
+impl From<reqwest::Error> for Error {
+    fn from(error: reqwest::Error) -> Self {
+        if error.is_timeout() {
+            Error::Timeout(error.to_string())
+        } else {
+            Error::Upstream(error.to_string())
+        }
+    }
+}
+
+impl Error {
+    // Stable identifier for the variant, safe to store and hand to clients
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Error::WrongCredentials => "wrong_credentials",
+            Error::TokenMismatch => "token_mismatch",
+            Error::Unauthorized => "unauthorized",
+            Error::NotFound(_) => "not_found",
+            Error::EmailInUse => "email_in_use",
+            Error::Forbidden(_) => "forbidden",
+            Error::BadRequest(_) => "bad_request",
+            Error::InvalidFields(_) => "invalid_fields",
+            Error::InvalidBody(_) => "invalid_body",
+            Error::PayloadTooLarge(_) => "payload_too_large",
+            Error::Upstream(_) => "upstream",
+            Error::Timeout(_) => "timeout",
+            Error::StoreData(_) => "store_data",
+            Error::Llm(_) => "llm",
+            Error::Storage(_) => "storage",
+            Error::Mail(_) => "mail",
+            Error::Config(_) => "config",
+            Error::RateLimited(_) => "rate_limited",
+            Error::PasswordHash(_) => "password_hash",
+            Error::SurrealDB(_) => "database",
+            Error::Jwt(_) => "jwt",
+            Error::Deserialize(_) => "deserialize",
+            Error::ParseSurrealId(_) => "invalid_id",
+        }
+    }
+
+    pub fn is_client_error(&self) -> bool {
+        actix_web::ResponseError::status_code(self).is_client_error()
+    }
+
+    pub fn is_internal(&self) -> bool {
+        !self.is_client_error()
+    }
+
+    pub fn body<'a>(&'a self, request_id: Option<&'a str>) -> ErrorBody<'a> {
+        let message = if self.is_internal() {
+            INTERNAL_MESSAGE.to_string()
+        } else {
+            self.to_string()
+        };
+        let details = match self {
+            Error::InvalidFields(fields) => Some(fields),
+            _ => None,
+        };
+
+        ErrorBody {
+            code: self.code(),
+            message,
+            details,
+            request_id,
+        }
+    }
+
+    pub fn response(&self, request_id: Option<&str>) -> HttpResponse {
+        let mut response = HttpResponse::build(actix_web::ResponseError::status_code(self));
+
+        if let Error::RateLimited(retry_after) = self {
+            response.insert_header((RETRY_AFTER, retry_after.to_string()));
+        }
+
+        response.json(ErrorEnvelope {
+            error: self.body(request_id),
+        })
+    }
+}
+
+// Every error leaves the server as `{ "error": { "code", "message", "details", "requestId" } }`
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorBody<'a> {
+    pub code: &'a str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<&'a BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<&'a str>,
+}
+
+impl ErrorBody<'_> {
+    // For errors raised by actix itself, like a malformed JSON body, which have no code of their own
+    pub fn from_status(status: StatusCode, message: String) -> ErrorBody<'static> {
+        let code = match status {
+            StatusCode::UNAUTHORIZED => "unauthorized",
+            StatusCode::FORBIDDEN => "forbidden",
+            StatusCode::NOT_FOUND => "not_found",
+            StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+            StatusCode::TOO_MANY_REQUESTS => "rate_limited",
+            status if status.is_client_error() => "bad_request",
+            _ => "internal",
+        };
+        let message = if status.is_client_error() {
+            message
+        } else {
+            INTERNAL_MESSAGE.to_string()
+        };
+
+        ErrorBody {
+            code,
+            message,
+            details: None,
+            request_id: None,
         }
     }
 }
 
+#[derive(Serialize, Debug)]
+pub struct ErrorEnvelope<'a> {
+    pub error: ErrorBody<'a>,
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use actix_web::ResponseError;
+    use serde_json::json;
+
+    use super::*;
+
+    fn cases() -> Vec<(Error, StatusCode, &'static str)> {
+        vec![
+            (
+                Error::BadRequest("x".into()),
+                StatusCode::BAD_REQUEST,
+                "bad_request",
+            ),
+            (
+                Error::InvalidFields(BTreeMap::new()),
+                StatusCode::BAD_REQUEST,
+                "invalid_fields",
+            ),
+            (
+                Error::InvalidBody("x".into()),
+                StatusCode::BAD_REQUEST,
+                "invalid_body",
+            ),
+            (
+                surrealitos::SurrealId::from_str("not an id")
+                    .unwrap_err()
+                    .into(),
+                StatusCode::BAD_REQUEST,
+                "invalid_id",
+            ),
+            (
+                Error::WrongCredentials,
+                StatusCode::UNAUTHORIZED,
+                "wrong_credentials",
+            ),
+            (
+                Error::TokenMismatch,
+                StatusCode::UNAUTHORIZED,
+                "token_mismatch",
+            ),
+            (
+                Error::Unauthorized,
+                StatusCode::UNAUTHORIZED,
+                "unauthorized",
+            ),
+            (
+                jsonwebtoken::errors::Error::from(jsonwebtoken::errors::ErrorKind::InvalidToken)
+                    .into(),
+                StatusCode::UNAUTHORIZED,
+                "jwt",
+            ),
+            (
+                Error::Forbidden("x".into()),
+                StatusCode::FORBIDDEN,
+                "forbidden",
+            ),
+            (
+                Error::NotFound("User".into()),
+                StatusCode::NOT_FOUND,
+                "not_found",
+            ),
+            (Error::EmailInUse, StatusCode::CONFLICT, "email_in_use"),
+            (
+                Error::PayloadTooLarge(1024),
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "payload_too_large",
+            ),
+            (
+                Error::RateLimited(30),
+                StatusCode::TOO_MANY_REQUESTS,
+                "rate_limited",
+            ),
+            (
+                Error::StoreData("x".into()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "store_data",
+            ),
+            (
+                Error::Storage("x".into()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "storage",
+            ),
+            (
+                Error::Mail("x".into()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "mail",
+            ),
+            (
+                Error::Config("x".into()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "config",
+            ),
+            (
+                surrealdb::Error::Api(surrealdb::error::Api::Query("x".into())).into(),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "database",
+            ),
+            (
+                serde_json::from_str::<u8>("x").unwrap_err().into(),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "deserialize",
+            ),
+            (
+                argon2::password_hash::Error::Password.into(),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "password_hash",
+            ),
+            (
+                Error::Upstream("x".into()),
+                StatusCode::BAD_GATEWAY,
+                "upstream",
+            ),
+            (Error::Llm("x".into()), StatusCode::BAD_GATEWAY, "llm"),
+            (
+                Error::Timeout("x".into()),
+                StatusCode::GATEWAY_TIMEOUT,
+                "timeout",
+            ),
+        ]
+    }
+
+    #[test]
+    fn maps_every_variant_to_its_status_and_code() {
+        for (error, status, code) in cases() {
+            assert_eq!(error.status_code(), status, "{error:?}");
+            assert_eq!(error.code(), code, "{error:?}");
+            assert_eq!(
+                error.is_client_error(),
+                status.is_client_error(),
+                "{error:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn reqwest_errors_are_upstream_failures() {
+        let error: Error = reqwest::Client::new()
+            .get("not a url")
+            .build()
+            .unwrap_err()
+            .into();
+
+        assert_eq!(error.status_code(), StatusCode::BAD_GATEWAY);
+        assert_eq!(error.code(), "upstream");
+    }
+
+    #[test]
+    fn client_errors_keep_their_message() {
+        let error = Error::NotFound("Transcription".into());
+        let body = serde_json::to_value(ErrorEnvelope {
+            error: error.body(Some("abc")),
+        })
+        .unwrap();
+
+        assert_eq!(
+            body,
+            json!({
+                "error": {
+                    "code": "not_found",
+                    "message": "Transcription not found",
+                    "requestId": "abc",
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn server_errors_hide_their_message() {
+        let error = Error::Config("JWT_SECRET is missing".into());
+        let body = error.body(None);
+
+        assert_eq!(body.code, "config");
+        assert_eq!(body.message, INTERNAL_MESSAGE);
+    }
+
+    #[test]
+    fn invalid_fields_come_back_as_details() {
+        let fields = BTreeMap::from([("email".to_string(), "is required".to_string())]);
+        let error = Error::InvalidFields(fields);
+        let body = serde_json::to_value(error.body(None)).unwrap();
+
+        assert_eq!(body["details"], json!({ "email": "is required" }));
+    }
+
+    #[test]
+    fn rate_limited_sets_retry_after() {
+        let response = Error::RateLimited(42).error_response();
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers().get(RETRY_AFTER).unwrap(), "42");
+    }
+
+    #[test]
+    fn actix_errors_get_a_code_from_their_status() {
+        let cases = [
+            (StatusCode::UNAUTHORIZED, "unauthorized"),
+            (StatusCode::FORBIDDEN, "forbidden"),
+            (StatusCode::NOT_FOUND, "not_found"),
+            (StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large"),
+            (StatusCode::TOO_MANY_REQUESTS, "rate_limited"),
+            (StatusCode::UNSUPPORTED_MEDIA_TYPE, "bad_request"),
+            (StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+        ];
+
+        for (status, code) in cases {
+            let body = ErrorBody::from_status(status, "message".to_string());
+            assert_eq!(body.code, code, "{status}");
+        }
+
+        let body = ErrorBody::from_status(StatusCode::SERVICE_UNAVAILABLE, "secret".to_string());
+        assert_eq!(body.message, INTERNAL_MESSAGE);
+    }
+}
@@ -0,0 +1,264 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    model::transcription::{Segment, Transcription},
+};
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Srt,
+    Vtt,
+    #[serde(alias = "markdown")]
+    Md,
+    #[serde(alias = "text")]
+    Txt,
+    #[default]
+    Json,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Srt => "application/x-subrip; charset=utf-8",
+            ExportFormat::Vtt => "text/vtt; charset=utf-8",
+            ExportFormat::Md => "text/markdown; charset=utf-8",
+            ExportFormat::Txt => "text/plain; charset=utf-8",
+            ExportFormat::Json => "application/json",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Srt => "srt",
+            ExportFormat::Vtt => "vtt",
+            ExportFormat::Md => "md",
+            ExportFormat::Txt => "txt",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+// Splits seconds into hours, minutes, seconds and milliseconds
+fn clock_parts(seconds: f64) -> (u64, u64, u64, u64) {
+    let total_millis = (seconds.max(0.0) * 1000.0).round() as u64;
+    let millis = total_millis % 1000;
+    let total_seconds = total_millis / 1000;
+
+    (
+        total_seconds / 3600,
+        (total_seconds % 3600) / 60,
+        total_seconds % 60,
+        millis,
+    )
+}
+
+fn srt_timestamp(seconds: f64) -> String {
+    let (hours, minutes, seconds, millis) = clock_parts(seconds);
+    format!("{hours:02}:{minutes:02}:{seconds:02},{millis:03}")
+}
+
+fn vtt_timestamp(seconds: f64) -> String {
+    let (hours, minutes, seconds, millis) = clock_parts(seconds);
+    format!("{hours:02}:{minutes:02}:{seconds:02}.{millis:03}")
+}
+
+fn short_timestamp(seconds: f64) -> String {
+    let (hours, minutes, seconds, _) = clock_parts(seconds);
+    format!("{hours:02}:{minutes:02}:{seconds:02}")
+}
+
+// Diarized segments carry speakers, the raw Mistral ones are the fallback
+fn segments(transcription: &Transcription) -> &[Segment] {
+    transcription
+        .diarized
+        .as_deref()
+        .or(transcription.segments.as_deref())
+        .unwrap_or_default()
+}
+
+fn subtitle_segments(transcription: &Transcription) -> Result<&[Segment]> {
+    let segments = segments(transcription);
+    if segments.is_empty() {
+        return Err(Error::BadRequest(
+            "Transcription has no timed segments to export".to_string(),
+        ));
+    }
+
+    Ok(segments)
+}
+
+fn speaker_line(segment: &Segment) -> String {
+    match &segment.speaker {
+        Some(speaker) => format!("{speaker}: {}", segment.text.trim()),
+        None => segment.text.trim().to_string(),
+    }
+}
+
+fn srt_cues(segments: &[Segment]) -> String {
+    segments
+        .iter()
+        .enumerate()
+        .map(|(index, segment)| {
+            format!(
+                "{}\n{} --> {}\n{}\n",
+                index + 1,
+                srt_timestamp(segment.start),
+                srt_timestamp(segment.end),
+                speaker_line(segment)
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn vtt_cues(segments: &[Segment]) -> String {
+    let cues: Vec<String> = segments
+        .iter()
+        .map(|segment| {
+            let text = match &segment.speaker {
+                Some(speaker) => format!("<v {speaker}>{}", segment.text.trim()),
+                None => segment.text.trim().to_string(),
+            };
+
+            format!(
+                "{} --> {}\n{}\n",
+                vtt_timestamp(segment.start),
+                vtt_timestamp(segment.end),
+                text
+            )
+        })
+        .collect();
+
+    format!("WEBVTT\n\n{}", cues.join("\n"))
+}
+
+pub fn to_srt(transcription: &Transcription) -> Result<String> {
+    Ok(srt_cues(subtitle_segments(transcription)?))
+}
+
+pub fn to_vtt(transcription: &Transcription) -> Result<String> {
+    Ok(vtt_cues(subtitle_segments(transcription)?))
+}
+
+pub fn to_txt(transcription: &Transcription) -> String {
+    let segments = segments(transcription);
+    if segments.is_empty() {
+        return transcription.raw.clone().unwrap_or_default();
+    }
+
+    segments
+        .iter()
+        .map(|segment| {
+            format!(
+                "[{}] {}",
+                short_timestamp(segment.start),
+                speaker_line(segment)
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+pub fn to_markdown(transcription: &Transcription) -> String {
+    let mut markdown = String::new();
+
+    // Notes already come as markdown with their own title
+    match &transcription.note {
+        Some(note) => markdown.push_str(note.trim_end()),
+        None => markdown.push_str("# Transcription"),
+    }
+
+    markdown.push_str(&format!(
+        "\n\n_Recorded {}_\n\n## Transcript\n\n",
+        transcription.created_at
+    ));
+
+    let segments = segments(transcription);
+    if segments.is_empty() {
+        markdown.push_str(transcription.raw.as_deref().unwrap_or_default());
+        markdown.push('\n');
+        return markdown;
+    }
+
+    for segment in segments {
+        let speaker = segment.speaker.as_deref().unwrap_or("Speaker");
+        markdown.push_str(&format!(
+            "**{speaker}** `{}`: {}\n\n",
+            short_timestamp(segment.start),
+            segment.text.trim()
+        ));
+    }
+
+    markdown
+}
+
+pub fn export(transcription: &Transcription, format: &ExportFormat) -> Result<String> {
+    match format {
+        ExportFormat::Srt => to_srt(transcription),
+        ExportFormat::Vtt => to_vtt(transcription),
+        ExportFormat::Md => Ok(to_markdown(transcription)),
+        ExportFormat::Txt => Ok(to_txt(transcription)),
+        ExportFormat::Json => Ok(serde_json::to_string_pretty(transcription)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(start: f64, end: f64, text: &str, speaker: Option<&str>) -> Segment {
+        Segment {
+            text: text.to_string(),
+            start,
+            end,
+            speaker: speaker.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn formats_srt_timestamps() {
+        assert_eq!(srt_timestamp(0.0), "00:00:00,000");
+        assert_eq!(srt_timestamp(1.5), "00:00:01,500");
+        assert_eq!(srt_timestamp(61.042), "00:01:01,042");
+        assert_eq!(srt_timestamp(3599.999), "00:59:59,999");
+        assert_eq!(srt_timestamp(3600.0), "01:00:00,000");
+        assert_eq!(srt_timestamp(-2.0), "00:00:00,000");
+    }
+
+    #[test]
+    fn formats_vtt_timestamps() {
+        assert_eq!(vtt_timestamp(0.25), "00:00:00.250");
+        assert_eq!(vtt_timestamp(59.9996), "00:01:00.000");
+        assert_eq!(vtt_timestamp(7322.007), "02:02:02.007");
+    }
+
+    #[test]
+    fn numbers_srt_cues_and_labels_speakers() {
+        let segments = [
+            segment(0.0, 2.5, " Hello there ", Some("Ana")),
+            segment(2.5, 4.0, "General Kenobi", None),
+        ];
+
+        assert_eq!(
+            srt_cues(&segments),
+            "1\n00:00:00,000 --> 00:00:02,500\nAna: Hello there\n\n\
+             2\n00:00:02,500 --> 00:00:04,000\nGeneral Kenobi\n"
+        );
+    }
+
+    #[test]
+    fn writes_vtt_header_and_voice_tags() {
+        let segments = [
+            segment(0.0, 1.0, "Hi", Some("Ana")),
+            segment(1.0, 2.0, "Hey", None),
+        ];
+
+        assert_eq!(
+            vtt_cues(&segments),
+            "WEBVTT\n\n00:00:00.000 --> 00:00:01.000\n<v Ana>Hi\n\n\
+             00:00:01.000 --> 00:00:02.000\nHey\n"
+        );
+    }
+}
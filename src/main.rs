@@ -1,53 +1,26 @@
 mod api;
+mod config;
 mod connector;
 mod error;
+mod export;
 mod model;
+mod pipeline;
 mod repo;
 
 use actix_cors::Cors;
 use actix_web::{
-    App, Error, HttpMessage, HttpServer,
-    dev::ServiceRequest,
-    middleware::Logger,
-    web::{Data, scope},
+    App, HttpServer,
+    middleware::{Logger, from_fn},
+    web::Data,
 };
-// use actix_web_grants::permissions::AttachPermissions;
-use actix_web_httpauth::{extractors::bearer::BearerAuth, middleware::HttpAuthentication};
 
-use api::{
-    auth::{login, signup},
-    user::{delete_user, get_user, update_user},
-};
+use api::{request_id::request_id, routes};
+use config::AppConfig;
+use connector::mailer::Mailer;
 use dotenv::dotenv;
-use model::token::TokenManager;
+use pipeline::worker::Worker;
 use repo::surreal::SurrealDB;
 
-use crate::api::{
-    auth::{check_email_exists, guest, refresh, validate_token},
-    storage::{presign_get, presign_put},
-    transcription::{
-        diarize_webhook, get_transcription, get_user_transcriptions, transcribe,
-        transcribe_raw_only,
-    },
-};
-
-async fn validator(
-    req: ServiceRequest,
-    credentials: BearerAuth,
-) -> Result<ServiceRequest, (Error, ServiceRequest)> {
-    let db = req.app_data::<Data<SurrealDB>>().unwrap();
-    let result = TokenManager::validate_access_token(&db.surreal, credentials.token()).await;
-
-    match result {
-        Ok(claims) => {
-            // req.attach(claims.permissions.clone());
-            req.extensions_mut().insert(claims);
-            Ok(req)
-        }
-        Err(_) => Err((crate::error::Error::Unauthorized.into(), req)),
-    }
-}
-
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
@@ -57,16 +30,40 @@ async fn main() -> std::io::Result<()> {
     }
     env_logger::init();
 
-    let surreal = SurrealDB::init()
+    let config = match AppConfig::load() {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
+    };
+    config::init(config);
+    let config = config::get();
+    let bind_address = (config.server.host.clone(), config.server.port);
+
+    let surreal = SurrealDB::init(config)
         .await
         .expect("Error connecting to SurrealDB");
     let surreal_data = Data::new(surreal);
 
+    // `echo migrate` only applies pending migrations, which connecting already did
+    if std::env::args().nth(1).as_deref() == Some("migrate") {
+        return Ok(());
+    }
+
+    let mailer: Data<dyn Mailer> = Data::from(
+        connector::mailer::from_config(&config.mail).expect("Error configuring mail transport"),
+    );
+
+    let worker = Worker::new(surreal_data.surreal.clone());
+    actix_web::rt::spawn(worker.run());
+
     HttpServer::new(move || {
-        let auth = HttpAuthentication::bearer(validator);
         let logger = Logger::default();
         App::new()
             .app_data(Data::clone(&surreal_data))
+            .app_data(Data::clone(&mailer))
+            .wrap(from_fn(request_id))
             .wrap(logger)
             .wrap(
                 Cors::default()
@@ -79,36 +76,9 @@ async fn main() -> std::io::Result<()> {
                     ])
                     .max_age(3600),
             )
-            .service(
-                scope("/auth")
-                    .service(login)
-                    .service(signup)
-                    .service(refresh)
-                    .service(guest)
-                    .service(check_email_exists),
-            )
-            .service(
-                scope("/api")
-                    .wrap(auth)
-                    .service(scope("/auth").service(validate_token))
-                    .service(
-                        scope("/user")
-                            .service(get_user)
-                            .service(update_user)
-                            .service(delete_user),
-                    )
-                    .service(scope("/storage").service(presign_put).service(presign_get))
-                    .service(
-                        scope("/transcription")
-                            .service(get_user_transcriptions)
-                            .service(get_transcription)
-                            .service(transcribe_raw_only)
-                            .service(transcribe),
-                    ),
-            )
-            .service(scope("webhook").service(diarize_webhook))
+            .configure(routes::configure)
     })
-    .bind(("0.0.0.0", 8080))?
+    .bind(bind_address)?
     .run()
     .await
 }
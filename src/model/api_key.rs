@@ -0,0 +1,144 @@
+use std::str::FromStr;
+
+use chrono::{Duration, Utc};
+use rand::distributions::{Alphanumeric, DistString};
+use serde::{Deserialize, Serialize};
+use surrealdb::{Surreal, engine::any::Any, sql::Datetime};
+use surrealitos::SurrealId;
+
+use crate::{
+    error::{Error, Result},
+    model::{
+        Controller,
+        one_time_token::hash_token,
+        token::Claims,
+        user::{User, UserController},
+    },
+};
+
+// Lets the validator tell keys apart from encrypted access tokens, which are hex
+pub const API_KEY_PREFIX: &str = "echo_";
+
+// Only the hash is stored, the plaintext goes to the user once
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct ApiKey {
+    pub id: SurrealId,
+    pub name: String,
+    pub hint: String, // Last characters of the key so users can tell them apart
+    pub scopes: Vec<String>,
+    #[serde(skip_serializing)]
+    pub key_hash: String,
+    pub user_id: String,
+    pub expires_at: Option<String>,
+    pub last_used_at: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct NewApiKey {
+    name: String,
+    hint: String,
+    scopes: Vec<String>,
+    key_hash: String,
+    user_id: String,
+    expires_at: Option<Datetime>,
+    created_at: Datetime,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+    pub key: String,
+}
+
+pub struct ApiKeyController;
+
+impl ApiKeyController {
+    // Scopes can't go beyond the permissions of the session creating the key
+    pub async fn create(
+        client: &Surreal<Any>,
+        user: &User,
+        permissions: &[String],
+        name: &str,
+        scopes: Vec<String>,
+        ttl: Option<Duration>,
+    ) -> Result<CreatedApiKey> {
+        if let Some(scope) = scopes.iter().find(|scope| !permissions.contains(scope)) {
+            return Err(Error::Forbidden(format!("Scope {scope} is not available")));
+        }
+
+        let key = format!(
+            "{API_KEY_PREFIX}{}",
+            Alphanumeric.sample_string(&mut rand::thread_rng(), 40)
+        );
+
+        let new_key = NewApiKey {
+            name: name.to_owned(),
+            hint: key[key.len() - 4..].to_string(),
+            scopes,
+            key_hash: hash_token(&key),
+            user_id: user.id.to_string(),
+            expires_at: ttl.map(|ttl| Datetime::from(Utc::now() + ttl)),
+            created_at: Datetime::default(),
+        };
+
+        let stored: Option<ApiKey> = client.create("api_key").content(new_key).await?;
+        let api_key = stored.ok_or(Error::StoreData("api_key".to_string()))?;
+
+        Ok(CreatedApiKey { api_key, key })
+    }
+
+    pub async fn get_by_user(client: &Surreal<Any>, user_id: &str) -> Result<Vec<ApiKey>> {
+        let mut results = client
+            .query("SELECT * FROM api_key WHERE user_id = $user_id ORDER BY created_at DESC")
+            .bind(("user_id", user_id.to_owned()))
+            .await?;
+
+        let keys: Vec<ApiKey> = results.take(0)?;
+        Ok(keys)
+    }
+
+    // Returns whether the user owned a key with that id. The id comes from the path, so a
+    // record of another table is treated as a missing key instead of being deleted
+    pub async fn delete(client: &Surreal<Any>, user_id: &str, id: &SurrealId) -> Result<bool> {
+        if id.0.tb != "api_key" {
+            return Ok(false);
+        }
+
+        let mut results = client
+            .query("DELETE $api_key WHERE user_id = $user_id RETURN BEFORE")
+            .bind(("api_key", id.clone().0))
+            .bind(("user_id", user_id.to_owned()))
+            .await?;
+
+        let deleted: Vec<ApiKey> = results.take(0)?;
+        Ok(!deleted.is_empty())
+    }
+
+    // Marks the key as used in the same statement that looks it up
+    pub async fn validate(client: &Surreal<Any>, key: &str) -> Result<Claims> {
+        let mut results = client
+            .query("UPDATE api_key SET last_used_at = time::now() WHERE key_hash = $key_hash AND (expires_at IS NONE OR expires_at > time::now())")
+            .bind(("key_hash", hash_token(key)))
+            .await?;
+
+        let keys: Vec<ApiKey> = results.take(0)?;
+        let api_key = keys.into_iter().next().ok_or(Error::TokenMismatch)?;
+
+        let user_id = SurrealId::from_str(&api_key.user_id)?;
+        let user = UserController::get(client, &user_id)
+            .await?
+            .ok_or(Error::TokenMismatch)?;
+
+        // Same claims as an access token, narrowed down to the key's scopes
+        let mut claims = Claims::new(user, Duration::hours(3));
+        claims
+            .permissions
+            .retain(|permission| api_key.scopes.contains(permission));
+
+        Ok(claims)
+    }
+}
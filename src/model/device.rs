@@ -1,7 +1,7 @@
 use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
-use surrealdb::{Surreal, engine::remote::ws::Client};
+use surrealdb::{Surreal, engine::any::Any};
 use surrealitos::{SurrealId, extract_id};
 
 use crate::{
@@ -61,7 +61,7 @@ impl From<&NewDevice> for DevicePatch {
 }
 
 impl Device {
-    pub async fn get_guest(&self, client: &Surreal<Client>) -> Result<Option<User>> {
+    pub async fn get_guest(&self, client: &Surreal<Any>) -> Result<Option<User>> {
         let guest_id = self.guest_id.clone();
         if guest_id.is_none() {
             return Ok(None);
@@ -81,17 +81,14 @@ impl Device {
 pub struct DeviceController;
 
 impl DeviceController {
-    pub async fn get(client: &Surreal<Client>, id: &str) -> Result<Option<Device>> {
+    pub async fn get(client: &Surreal<Any>, id: &str) -> Result<Option<Device>> {
         let device_id = extract_id(id, "device");
 
         let device: Option<Device> = client.select(("device", device_id)).await?;
         Ok(device)
     }
 
-    pub async fn create_or_update(
-        client: &Surreal<Client>,
-        new_device: &NewDevice,
-    ) -> Result<Device> {
+    pub async fn create_or_update(client: &Surreal<Any>, new_device: &NewDevice) -> Result<Device> {
         let stored_device = Self::get(client, &new_device.id).await?;
 
         let device: Device = match stored_device {
@@ -115,8 +112,17 @@ impl DeviceController {
         Ok(device)
     }
 
+    // Devices that signed in as the guest now belong to the registered user
+    pub async fn release_guest(client: &Surreal<Any>, user_id: &str) -> Result<()> {
+        client
+            .query("UPDATE device SET user_id = $user_id, guest_id = NONE WHERE guest_id = $user_id OR user_id = $user_id RETURN NONE")
+            .bind(("user_id", user_id.to_owned()))
+            .await?;
+        Ok(())
+    }
+
     pub async fn update(
-        client: &Surreal<Client>,
+        client: &Surreal<Any>,
         id: &str,
         device_patch: &DevicePatch,
     ) -> Result<Device> {
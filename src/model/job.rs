@@ -0,0 +1,221 @@
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use surrealdb::{Surreal, engine::any::Any, sql::Datetime};
+use surrealitos::{SurrealId, extract_id, serialize_as_optional_record};
+
+use crate::{
+    error::{Error, Result},
+    model::{Controller, transcription::Step},
+};
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Waiting, // Handed off to an external service, finished through a webhook
+    Done,
+    Failed,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct Job {
+    pub id: SurrealId,
+    pub created_at: String,
+    pub updated_at: String,
+    pub transcription: SurrealId,
+    pub step: Step,
+    pub status: JobStatus,
+    pub attempts: u32,
+    pub max_attempts: u32,
+    pub run_at: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub call_id: Option<String>, // Modal call while Waiting
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+pub struct NewJob {
+    pub step: Option<Step>,
+    pub status: Option<JobStatus>,
+    pub attempts: Option<u32>,
+    pub max_attempts: Option<u32>,
+
+    #[serde(skip_deserializing, serialize_with = "serialize_as_optional_record")]
+    pub transcription: Option<SurrealId>,
+
+    #[serde(skip_deserializing)]
+    pub run_at: Option<Datetime>,
+    #[serde(skip_deserializing)]
+    pub created_at: Option<Datetime>,
+    #[serde(skip_deserializing)]
+    pub updated_at: Option<Datetime>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+pub struct JobPatch {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<JobStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub call_id: Option<String>,
+
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub run_at: Option<Datetime>,
+    #[serde(skip_deserializing)]
+    pub updated_at: Option<Datetime>,
+}
+
+pub struct JobController;
+
+#[async_trait]
+impl Controller<Job, NewJob, JobPatch> for JobController {
+    async fn get(client: &Surreal<Any>, id: &SurrealId) -> Result<Option<Job>> {
+        let mut results = client
+            .query("SELECT * FROM ONLY $job")
+            .bind(("job", id.clone().0))
+            .await?;
+
+        let job: Option<Job> = results.take(0)?;
+        Ok(job)
+    }
+
+    async fn create(client: &Surreal<Any>, new_job: &NewJob) -> Result<Job> {
+        let mut job_data = new_job.clone();
+        job_data.status = Some(job_data.status.unwrap_or(JobStatus::Queued));
+        job_data.attempts = Some(job_data.attempts.unwrap_or(0));
+        job_data.max_attempts = Some(job_data.max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS));
+        job_data.run_at = Some(job_data.run_at.unwrap_or_default());
+        job_data.created_at = Some(Datetime::default());
+        job_data.updated_at = Some(Datetime::default());
+
+        let job: Option<Job> = client.create("job").content(job_data).await?;
+        job.ok_or(Error::StoreData("job".to_string()))
+    }
+
+    async fn update(client: &Surreal<Any>, id: &str, job_patch: &JobPatch) -> Result<Job> {
+        let job_id = extract_id(id, "job");
+        let mut job_data = job_patch.clone();
+
+        job_data.updated_at = Some(Datetime::default());
+
+        let job_opt: Option<Job> = client.update(("job", job_id)).merge(job_data).await?;
+        job_opt.ok_or(Error::StoreData("job".to_string()))
+    }
+
+    async fn delete(client: &Surreal<Any>, id: &SurrealId) -> Result<()> {
+        client
+            .query("DELETE $job RETURN NONE")
+            .bind(("job", id.clone().0))
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl JobController {
+    pub async fn enqueue(
+        client: &Surreal<Any>,
+        transcription_id: &SurrealId,
+        step: Step,
+    ) -> Result<Job> {
+        let new_job = NewJob {
+            step: Some(step),
+            transcription: Some(transcription_id.clone()),
+            ..Default::default()
+        };
+
+        Self::create(client, &new_job).await
+    }
+
+    // Marks the oldest due job as Running, the WHERE clause keeps two workers from claiming the same job
+    pub async fn claim_next(client: &Surreal<Any>) -> Result<Option<Job>> {
+        let mut results = client
+            .query("SELECT * FROM job WHERE status = 'Queued' AND run_at <= time::now() ORDER BY run_at LIMIT 1")
+            .await?;
+
+        let candidate: Option<Job> = results.take(0)?;
+        let Some(candidate) = candidate else {
+            return Ok(None);
+        };
+
+        let mut results = client
+            .query("UPDATE $job SET status = 'Running', attempts += 1, updated_at = time::now() WHERE status = 'Queued'")
+            .bind(("job", candidate.id.0))
+            .await?;
+
+        let claimed: Vec<Job> = results.take(0)?;
+        Ok(claimed.into_iter().next())
+    }
+
+    pub async fn retry_later(client: &Surreal<Any>, job: &Job, error: &Error) -> Result<Job> {
+        // Back off exponentially: 30s, 60s, 120s...
+        let delay = Duration::seconds(30 * 2_i64.pow(job.attempts.saturating_sub(1)));
+
+        let patch = JobPatch {
+            status: Some(JobStatus::Queued),
+            last_error: Some(error.to_string()),
+            run_at: Some(Datetime::from(Utc::now() + delay)),
+            ..Default::default()
+        };
+
+        Self::update(client, &job.id.to_string(), &patch).await
+    }
+
+    // The webhook can beat the worker marking the job Waiting, so a Running job counts too
+    pub async fn get_dispatched(
+        client: &Surreal<Any>,
+        transcription_id: &SurrealId,
+        step: Step,
+    ) -> Result<Option<Job>> {
+        let mut results = client
+            .query("SELECT * FROM job WHERE transcription = $transcription AND step = $step AND status IN ['Running', 'Waiting'] ORDER BY created_at DESC LIMIT 1")
+            .bind(("transcription", transcription_id.clone().0))
+            .bind(("step", step))
+            .await?;
+
+        let job: Option<Job> = results.take(0)?;
+        Ok(job)
+    }
+
+    // Only a job still Running moves on, one the webhook already finished keeps its status
+    pub async fn mark_waiting(client: &Surreal<Any>, job: &Job, call_id: String) -> Result<()> {
+        client
+            .query("UPDATE $job SET status = 'Waiting', call_id = $call_id, updated_at = time::now() WHERE status = 'Running' RETURN NONE")
+            .bind(("job", job.id.clone().0))
+            .bind(("call_id", call_id))
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_active(
+        client: &Surreal<Any>,
+        transcription_id: &SurrealId,
+    ) -> Result<Vec<Job>> {
+        let mut results = client
+            .query("SELECT * FROM job WHERE transcription = $transcription AND status IN ['Queued', 'Running', 'Waiting']")
+            .bind(("transcription", transcription_id.clone().0))
+            .await?;
+
+        let jobs: Vec<Job> = results.take(0)?;
+        Ok(jobs)
+    }
+
+    // Jobs left Running by a crash or deploy go back to the queue, as do webhooks that never arrived
+    pub async fn resume_unfinished(client: &Surreal<Any>) -> Result<()> {
+        client
+            .query("UPDATE job SET status = 'Queued', run_at = time::now() WHERE status = 'Running' RETURN NONE")
+            .query("UPDATE job SET status = 'Queued', run_at = time::now() WHERE status = 'Waiting' AND updated_at < time::now() - 30m RETURN NONE")
+            .await?;
+
+        Ok(())
+    }
+}
@@ -1,14 +1,24 @@
+use std::str::FromStr;
+
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
-use surrealdb::{Surreal, engine::remote::ws::Client};
+use surrealdb::{Surreal, engine::any::Any};
 use surrealitos::SurrealId;
 
+use crate::error::Error;
+
+pub mod api_key;
 pub mod device;
+pub mod job;
+pub mod one_time_token;
+pub mod role;
+pub mod throttle;
 pub mod token;
 pub mod transcription;
+pub mod two_factor;
 pub mod user;
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub enum LLMProvider {
     OpenAI,
@@ -17,13 +27,27 @@ pub enum LLMProvider {
     Ollama, // For handling on desktop
 }
 
+impl FromStr for LLMProvider {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "openai" => Ok(LLMProvider::OpenAI),
+            "anthropic" => Ok(LLMProvider::Anthropic),
+            "google" => Ok(LLMProvider::Google),
+            "ollama" => Ok(LLMProvider::Ollama),
+            _ => Err(Error::BadRequest(format!("Unknown LLM provider {s}"))),
+        }
+    }
+}
+
 #[async_trait]
 pub trait Controller<T, NewT, PatchT> {
-    async fn get(client: &Surreal<Client>, id: &SurrealId) -> crate::error::Result<Option<T>>;
+    async fn get(client: &Surreal<Any>, id: &SurrealId) -> crate::error::Result<Option<T>>;
 
-    async fn create(client: &Surreal<Client>, new_entity: &NewT) -> crate::error::Result<T>;
+    async fn create(client: &Surreal<Any>, new_entity: &NewT) -> crate::error::Result<T>;
 
-    async fn update(client: &Surreal<Client>, id: &str, patch: &PatchT) -> crate::error::Result<T>;
+    async fn update(client: &Surreal<Any>, id: &str, patch: &PatchT) -> crate::error::Result<T>;
 
-    async fn delete(client: &Surreal<Client>, id: &SurrealId) -> crate::error::Result<()>;
+    async fn delete(client: &Surreal<Any>, id: &SurrealId) -> crate::error::Result<()>;
 }
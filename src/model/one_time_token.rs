@@ -0,0 +1,89 @@
+use chrono::{Duration, Utc};
+use rand::distributions::{Alphanumeric, DistString};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use surrealdb::{Surreal, engine::any::Any, sql::Datetime};
+use surrealitos::SurrealId;
+
+use crate::error::{Error, Result};
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub enum OneTimeTokenKind {
+    PasswordReset,
+    EmailVerification,
+    TwoFactorChallenge, // Issued by login when the password was right but a code is still needed
+}
+
+// Only the hash is stored, the plaintext goes to the user once
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OneTimeToken {
+    pub id: SurrealId,
+    pub user_id: String,
+    pub kind: OneTimeTokenKind,
+    pub token_hash: String,
+    pub expires_at: String,
+    pub created_at: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct NewOneTimeToken {
+    user_id: String,
+    kind: OneTimeTokenKind,
+    token_hash: String,
+    expires_at: Datetime,
+    created_at: Datetime,
+}
+
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+pub struct OneTimeTokenController;
+
+impl OneTimeTokenController {
+    // Replaces any outstanding token of the same kind and returns the plaintext
+    pub async fn issue(
+        client: &Surreal<Any>,
+        user_id: &str,
+        kind: OneTimeTokenKind,
+        ttl: Duration,
+    ) -> Result<String> {
+        let token = Alphanumeric.sample_string(&mut rand::thread_rng(), 48);
+
+        let new_token = NewOneTimeToken {
+            user_id: user_id.to_owned(),
+            kind: kind.clone(),
+            token_hash: hash_token(&token),
+            expires_at: Datetime::from(Utc::now() + ttl),
+            created_at: Datetime::default(),
+        };
+
+        client
+            .query("DELETE one_time_token WHERE user_id = $user_id AND kind = $kind")
+            .bind(("user_id", user_id.to_owned()))
+            .bind(("kind", kind))
+            .await?;
+
+        let stored: Option<OneTimeToken> =
+            client.create("one_time_token").content(new_token).await?;
+        stored.ok_or(Error::StoreData("one_time_token".to_string()))?;
+
+        Ok(token)
+    }
+
+    // Deleting and returning in one statement makes every token single use
+    pub async fn consume(
+        client: &Surreal<Any>,
+        token: &str,
+        kind: OneTimeTokenKind,
+    ) -> Result<OneTimeToken> {
+        let mut results = client
+            .query("DELETE one_time_token WHERE token_hash = $token_hash AND kind = $kind AND expires_at > time::now() RETURN BEFORE")
+            .bind(("token_hash", hash_token(token)))
+            .bind(("kind", kind))
+            .await?;
+
+        let deleted: Vec<OneTimeToken> = results.take(0)?;
+        deleted.into_iter().next().ok_or(Error::TokenMismatch)
+    }
+}
@@ -0,0 +1,42 @@
+use serde::{Deserialize, Serialize};
+
+use crate::model::user::UserType;
+
+// Routes check these through the protect macro, optional steps through AuthDetails
+pub const TRANSCRIBE: &str = "transcription:create";
+pub const SUMMARIZE: &str = "transcription:summarize";
+pub const USERS_READ: &str = "users:read";
+pub const USERS_WRITE: &str = "users:write";
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum Role {
+    User,
+    Guest,
+    Admin,
+    Support,
+}
+
+impl Role {
+    pub fn permissions(&self) -> &'static [&'static str] {
+        match self {
+            Role::Guest => &[TRANSCRIBE],
+            Role::User => &[TRANSCRIBE, SUMMARIZE],
+            Role::Support => &[USERS_READ],
+            Role::Admin => &[USERS_READ, USERS_WRITE],
+        }
+    }
+
+    // Admin and support are granted on top of the account type, which sets the base role
+    pub fn is_grantable(&self) -> bool {
+        matches!(self, Role::Admin | Role::Support)
+    }
+}
+
+impl From<&UserType> for Role {
+    fn from(user_type: &UserType) -> Self {
+        match user_type {
+            UserType::User => Role::User,
+            UserType::Guest => Role::Guest,
+        }
+    }
+}
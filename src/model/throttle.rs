@@ -0,0 +1,150 @@
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use surrealdb::{Surreal, engine::any::Any, sql::Datetime};
+
+use crate::error::{Error, Result};
+
+// How many attempts are free before every further one locks the key for twice as long
+pub struct Policy {
+    pub free_attempts: u32,
+    pub base_lockout: Duration,
+    pub max_lockout: Duration,
+    pub window: Duration, // Attempts older than this are forgotten
+}
+
+pub const LOGIN: Policy = Policy {
+    free_attempts: 5,
+    base_lockout: Duration::seconds(30),
+    max_lockout: Duration::hours(1),
+    window: Duration::minutes(15),
+};
+
+// Six digit codes are quick to guess, so the lockout starts sooner and grows from a minute
+pub const TWO_FACTOR: Policy = Policy {
+    free_attempts: 3,
+    base_lockout: Duration::minutes(1),
+    max_lockout: Duration::hours(1),
+    window: Duration::minutes(15),
+};
+
+// Every request sends an email, so a few are enough before the inbox is protected
+pub const PASSWORD_RESET: Policy = Policy {
+    free_attempts: 5,
+    base_lockout: Duration::minutes(5),
+    max_lockout: Duration::hours(1),
+    window: Duration::hours(1),
+};
+
+pub const EMAIL_EXISTS: Policy = Policy {
+    free_attempts: 20,
+    base_lockout: Duration::minutes(1),
+    max_lockout: Duration::hours(1),
+    window: Duration::minutes(10),
+};
+
+impl Policy {
+    fn lockout(&self, attempts: u32) -> Option<Duration> {
+        let over = attempts.checked_sub(self.free_attempts)?;
+        let lockout = self.base_lockout * 2_i32.saturating_pow(over.min(16));
+
+        Some(lockout.min(self.max_lockout))
+    }
+
+    // When each further attempt unlocks, starting with the first one that locks
+    fn locked_until(&self) -> Vec<Datetime> {
+        let now = Utc::now();
+        (0..=16)
+            .filter_map(|over| self.lockout(self.free_attempts + over))
+            .map(|lockout| Datetime::from(now + lockout))
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Attempt {
+    pub attempts: u32,
+    pub locked_until: Option<Datetime>,
+}
+
+// Keys look like `login:ip:<address>` or `login:email:<address>`, one record each
+pub fn key(scope: &str, kind: &str, value: &str) -> String {
+    format!("{scope}:{kind}:{}", value.to_lowercase())
+}
+
+pub struct ThrottleController;
+
+impl ThrottleController {
+    // Fails with the seconds left when any of the keys is locked
+    pub async fn check(client: &Surreal<Any>, keys: &[String]) -> Result<()> {
+        let mut results = client
+            .query("SELECT attempts, locked_until FROM throttle WHERE key IN $keys AND locked_until > time::now()")
+            .bind(("keys", keys.to_vec()))
+            .await?;
+
+        let locked: Vec<Attempt> = results.take(0)?;
+        let retry_after = locked
+            .iter()
+            .filter_map(|attempt| {
+                attempt
+                    .locked_until
+                    .as_ref()
+                    .map(|locked_until| locked_until.0)
+            })
+            .max()
+            .map(|locked_until| (locked_until - Utc::now()).num_seconds().max(1));
+
+        match retry_after {
+            Some(seconds) => Err(Error::RateLimited(seconds)),
+            None => Ok(()),
+        }
+    }
+
+    // Counts an attempt against the key, restarting the count once the window has passed.
+    // The lockout is picked in the same statement so concurrent attempts can't skip it
+    pub async fn record(client: &Surreal<Any>, key: &str, policy: &Policy) -> Result<()> {
+        client
+            .query("UPSERT type::thing('throttle', $key) SET key = $key, attempts = (IF updated_at > $window_start THEN attempts ELSE 0 END) + 1, updated_at = time::now(), locked_until = IF attempts >= $free_attempts THEN array::at($locked_until, math::min([attempts - $free_attempts, array::len($locked_until) - 1])) ELSE locked_until END RETURN NONE")
+            .bind(("key", key.to_owned()))
+            .bind(("window_start", Datetime::from(Utc::now() - policy.window)))
+            .bind(("free_attempts", policy.free_attempts))
+            .bind(("locked_until", policy.locked_until()))
+            .await?
+            .check()?;
+
+        Ok(())
+    }
+
+    pub async fn clear(client: &Surreal<Any>, key: &str) -> Result<()> {
+        client
+            .query("DELETE type::thing('throttle', $key)")
+            .bind(("key", key.to_owned()))
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn free_attempts_are_not_locked() {
+        assert_eq!(LOGIN.lockout(0), None);
+        assert_eq!(LOGIN.lockout(4), None);
+    }
+
+    #[test]
+    fn lockout_doubles_with_every_attempt() {
+        assert_eq!(LOGIN.lockout(5), Some(Duration::seconds(30)));
+        assert_eq!(LOGIN.lockout(6), Some(Duration::seconds(60)));
+        assert_eq!(LOGIN.lockout(7), Some(Duration::minutes(2)));
+        assert_eq!(LOGIN.lockout(11), Some(Duration::minutes(32)));
+    }
+
+    #[test]
+    fn lockout_is_capped() {
+        assert_eq!(LOGIN.lockout(12), Some(Duration::hours(1)));
+        assert_eq!(LOGIN.lockout(u32::MAX), Some(Duration::hours(1)));
+        assert_eq!(EMAIL_EXISTS.lockout(30), Some(Duration::hours(1)));
+    }
+}
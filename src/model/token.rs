@@ -1,5 +1,3 @@
-use std::env;
-
 use chacha20poly1305::{
     XChaCha20Poly1305,
     aead::{Aead, AeadCore, KeyInit, OsRng},
@@ -7,12 +5,18 @@ use chacha20poly1305::{
 use chrono::{Duration, Utc};
 use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode, encode};
 use serde::{Deserialize, Serialize};
-use surrealdb::{Surreal, engine::remote::ws::Client};
+use surrealdb::{Surreal, engine::any::Any, sql::Datetime};
 use surrealitos::{SurrealId, extract_id};
 
-use crate::error::{Error, Result};
+use crate::{
+    config,
+    error::{Error, Result},
+};
+
+use super::{one_time_token::hash_token, user::User};
 
-use super::user::User;
+// Also how long a retired refresh token is remembered, it has expired by then
+const REFRESH_TOKEN_DAYS: i64 = 30;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all(serialize = "camelCase"))]
@@ -44,6 +48,14 @@ pub struct NewToken {
     pub device_id: String,
 }
 
+// A refresh token the device already exchanged, looked up by its hash
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RotatedRefreshToken {
+    pub hash: String,
+    pub user_id: String,
+    pub device_id: String,
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all(serialize = "camelCase"))]
 pub struct TokenResponse {
@@ -66,12 +78,23 @@ pub struct Claims {
     pub iat: u128,
     pub exp: i64,
     pub sub: String,
-    // pub permissions: Vec<String>,
+    // Tokens issued before roles existed carry none until they are refreshed
+    #[serde(default)]
+    pub permissions: Vec<String>,
+    // Set by the validator, never part of the encoded token
+    #[serde(skip)]
+    pub origin: ClaimsOrigin,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ClaimsOrigin {
+    #[default]
+    Session,
+    ApiKey,
 }
 
 impl Claims {
     pub fn new(user: User, duration: Duration) -> Self {
-        // TODO: get permissions from user roles
         let timestamp = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
@@ -83,7 +106,8 @@ impl Claims {
             iat: timestamp,
             exp,
             sub: user.id.to_string(),
-            // permissions: user.permissions,
+            permissions: user.permissions(),
+            origin: ClaimsOrigin::Session,
         }
     }
 }
@@ -92,7 +116,7 @@ pub struct TokenManager;
 
 impl TokenManager {
     pub fn generate(claims: &Claims) -> Result<String> {
-        let secret = env::var("JWT_SECRET").unwrap();
+        let secret = &config::get().jwt_secret;
         let token = encode(
             &Header::default(),
             &claims,
@@ -109,30 +133,18 @@ impl TokenManager {
     //   Ok(decoded_token.claims)
     // }
 
-    pub async fn validate_access_token(
-        client: &Surreal<Client>,
-        encrypted_token: &str,
-    ) -> Result<Claims> {
-        let secret = env::var("JWT_SECRET").unwrap();
-        let stored_token = TokenController::get_by_access_token(client, encrypted_token).await?;
-
-        if stored_token.is_none() {
-            return Err(Error::TokenMismatch);
-        }
-
-        let db_token = stored_token.unwrap();
-        let key: &[u8] = &hex::decode(db_token.key.unwrap()).expect("Hex decode error");
-
-        let nonces = db_token.nonce.unwrap();
-        let nonce_parts: Vec<&str> = nonces.split(':').collect();
-        let nonce: &[u8] = &hex::decode(nonce_parts[0]).expect("Hex decode error");
+    // Tokens are stored as hex encoded XChaCha20 ciphertexts of the signed JWT
+    fn decrypt(key: &str, nonce: &str, encrypted_token: &str) -> Result<Claims> {
+        let secret = &config::get().jwt_secret;
+        let key: &[u8] = &hex::decode(key).expect("Hex decode error");
+        let nonce: &[u8] = &hex::decode(nonce).expect("Hex decode error");
 
         let cipher = XChaCha20Poly1305::new(key.into());
-        let enc_token = hex::decode(encrypted_token).expect("Error decoding token");
+        let enc_token = hex::decode(encrypted_token).map_err(|_| Error::TokenMismatch)?;
 
         let decrypted = cipher
             .decrypt(nonce.into(), enc_token.as_ref())
-            .expect("Decryption error");
+            .map_err(|_| Error::TokenMismatch)?;
         let decrypted_token = std::str::from_utf8(&decrypted).unwrap();
         let decoded_token = decode::<Claims>(
             decrypted_token,
@@ -143,38 +155,62 @@ impl TokenManager {
         Ok(decoded_token.claims)
     }
 
-    pub async fn validate_refresh_token(
-        client: &Surreal<Client>,
+    pub async fn validate_access_token(
+        client: &Surreal<Any>,
         encrypted_token: &str,
     ) -> Result<Claims> {
-        let secret = env::var("JWT_SECRET").unwrap();
-        let stored_token = TokenController::get_by_refresh_token(client, encrypted_token).await?;
+        let stored_token = TokenController::get_by_access_token(client, encrypted_token).await?;
 
         if stored_token.is_none() {
             return Err(Error::TokenMismatch);
         }
 
         let db_token = stored_token.unwrap();
-        let key: &[u8] = &hex::decode(db_token.key.unwrap()).expect("Hex decode error");
-
         let nonces = db_token.nonce.unwrap();
         let nonce_parts: Vec<&str> = nonces.split(':').collect();
-        let nonce: &[u8] = &hex::decode(nonce_parts[1]).expect("Hex decode error");
 
-        let cipher = XChaCha20Poly1305::new(key.into());
-        let enc_token = hex::decode(encrypted_token).expect("Error decoding token");
+        Self::decrypt(&db_token.key.unwrap(), nonce_parts[0], encrypted_token)
+    }
 
-        let decrypted = cipher
-            .decrypt(nonce.into(), enc_token.as_ref())
-            .expect("Decryption error");
-        let decrypted_token = std::str::from_utf8(&decrypted).unwrap();
-        let decoded_token = decode::<Claims>(
-            decrypted_token,
-            &DecodingKey::from_secret(secret.as_ref()),
-            &Validation::default(),
+    // Returns the stored token too, its device is the one the new pair is issued for
+    pub async fn validate_refresh_token(
+        client: &Surreal<Any>,
+        encrypted_token: &str,
+        device_id: &str,
+    ) -> Result<(Claims, Token)> {
+        let stored_token = TokenController::get_by_refresh_token(client, encrypted_token).await?;
+
+        let Some(db_token) = stored_token else {
+            // A refresh token that was already exchanged is being replayed, revoke its device
+            if let Some(family) =
+                TokenController::get_by_rotated_refresh_token(client, encrypted_token).await?
+            {
+                log::warn!(
+                    "Refresh token reuse detected for user {} on device {}, revoking the device",
+                    family.user_id,
+                    family.device_id
+                );
+                TokenController::delete_by_device(client, &family.user_id, &family.device_id)
+                    .await?;
+            }
+
+            return Err(Error::TokenMismatch);
+        };
+
+        if extract_id(&db_token.device_id, "device") != extract_id(device_id, "device") {
+            return Err(Error::TokenMismatch);
+        }
+
+        let nonces = db_token.nonce.clone().unwrap();
+        let nonce_parts: Vec<&str> = nonces.split(':').collect();
+
+        let claims = Self::decrypt(
+            db_token.key.as_ref().unwrap(),
+            nonce_parts[1],
+            encrypted_token,
         )?;
 
-        Ok(decoded_token.claims)
+        Ok((claims, db_token))
     }
 }
 
@@ -182,7 +218,7 @@ pub struct TokenController;
 
 impl TokenController {
     pub async fn get_by_access_token(
-        client: &Surreal<Client>,
+        client: &Surreal<Any>,
         access_token: &str,
     ) -> Result<Option<Token>> {
         let mut results = client
@@ -195,7 +231,7 @@ impl TokenController {
     }
 
     pub async fn get_by_refresh_token(
-        client: &Surreal<Client>,
+        client: &Surreal<Any>,
         refresh_token: &str,
     ) -> Result<Option<Token>> {
         let mut results = client
@@ -207,10 +243,42 @@ impl TokenController {
         Ok(token)
     }
 
-    pub async fn get_by_device(
-        client: &Surreal<Client>,
-        device_id: String,
-    ) -> Result<Option<Token>> {
+    pub async fn get_by_rotated_refresh_token(
+        client: &Surreal<Any>,
+        refresh_token: &str,
+    ) -> Result<Option<RotatedRefreshToken>> {
+        let mut results = client
+            .query("SELECT * FROM rotated_refresh_token WHERE hash = $hash")
+            .bind(("hash", hash_token(refresh_token)))
+            .await?;
+
+        let rotated: Option<RotatedRefreshToken> = results.take(0)?;
+        Ok(rotated)
+    }
+
+    // Remembers the exchanged refresh token and forgets the ones that expired meanwhile
+    async fn retire_refresh_token(client: &Surreal<Any>, token: &Token) -> Result<()> {
+        let Some(refresh_token) = &token.refresh_token else {
+            return Ok(());
+        };
+
+        client
+            .query("DELETE rotated_refresh_token WHERE created_at < $expired")
+            .query("INSERT IGNORE INTO rotated_refresh_token { hash: $hash, user_id: $user_id, device_id: $device_id, created_at: time::now() } RETURN NONE")
+            .bind((
+                "expired",
+                Datetime::from(Utc::now() - Duration::days(REFRESH_TOKEN_DAYS)),
+            ))
+            .bind(("hash", hash_token(refresh_token)))
+            .bind(("user_id", token.user_id.clone()))
+            .bind(("device_id", token.device_id.clone()))
+            .await?
+            .check()?;
+
+        Ok(())
+    }
+
+    pub async fn get_by_device(client: &Surreal<Any>, device_id: String) -> Result<Option<Token>> {
         let mut results = client
             .query("SELECT * FROM token WHERE device_id = $device_id")
             .bind(("device_id", device_id))
@@ -221,12 +289,12 @@ impl TokenController {
     }
 
     pub async fn create_or_update(
-        client: &Surreal<Client>,
+        client: &Surreal<Any>,
         user: &User,
         device_id: &str,
     ) -> Result<TokenResponse> {
         let claims = Claims::new(user.to_owned(), Duration::hours(3));
-        let refresh_claims = Claims::new(user.to_owned(), Duration::days(30));
+        let refresh_claims = Claims::new(user.to_owned(), Duration::days(REFRESH_TOKEN_DAYS));
 
         let access_token = TokenManager::generate(&claims)?;
         let refresh_token = TokenManager::generate(&refresh_claims)?;
@@ -264,87 +332,138 @@ impl TokenController {
                 token.ok_or(Error::StoreData("token".to_string()))?
             }
             Some(prev_token) => {
-                let token_id = extract_id(&prev_token.id.to_string(), "token");
-                let token: Option<Token> =
-                    client.update(("token", token_id)).merge(new_token).await?;
-                token.unwrap()
+                // Only one of two concurrent rotations of the same refresh token wins
+                let mut results = client
+                    .query("UPDATE $token MERGE $data WHERE refresh_token = $previous")
+                    .bind(("token", prev_token.id.clone().0))
+                    .bind(("data", new_token))
+                    .bind(("previous", prev_token.refresh_token.clone()))
+                    .await?;
+
+                let updated: Vec<Token> = results.take(0)?;
+                let token = updated.into_iter().next().ok_or(Error::TokenMismatch)?;
+
+                // The replaced refresh token is retired, replaying it revokes the device
+                Self::retire_refresh_token(client, &prev_token).await?;
+                token
             }
         };
 
         Ok(db_token.into())
     }
 
-    // Enable when we need email verification
-    // pub async fn create_verification_token(
-    //     client: &Surreal<Client>,
-    //     user: &User,
-    // ) -> Result<TokenResponse> {
-    //     let claims = Claims::new(user.to_owned());
-
-    //     let access_token = TokenManager::generate(&claims)?;
-
-    //     let key = XChaCha20Poly1305::generate_key(&mut OsRng);
-    //     let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
-
-    //     let cipher = XChaCha20Poly1305::new(&key);
-    //     let encrypted_access_token = cipher
-    //         .encrypt(&nonce, access_token.as_bytes().as_ref())
-    //         .expect("Encryption error");
-
-    //     let new_token = NewToken {
-    //         access_token: hex::encode(encrypted_access_token),
-    //         refresh_token: None,
-    //         key: Some(hex::encode(key)),
-    //         nonce: Some(hex::encode(nonce)),
-    //         user_id: user.id.to_raw(),
-    //         device_id: "Verification".to_string(),
-    //     };
-
-    //     let stored_token =
-    //         Self::get_verification_token_by_user(client, user.id.to_raw().as_str()).await?;
-
-    //     let db_token: Token = match stored_token {
-    //         None => {
-    //             let tokens: Vec<Token> = client.create("token").content(new_token).await?;
-    //             tokens
-    //                 .into_iter()
-    //                 .next()
-    //                 .ok_or(Error::StoreData("token".to_string()))?
-    //         }
-    //         Some(prev_token) => {
-    //             let token_id = extract_id(&prev_token.id.to_raw(), "token");
-    //             let token: Option<Token> =
-    //                 client.update(("token", token_id)).merge(new_token).await?;
-    //             token.unwrap()
-    //         }
-    //     };
-
-    //     Ok(db_token.into())
-    // }
+    // Signs the user out of every device
+    pub async fn delete_by_user(client: &Surreal<Any>, user_id: &str) -> Result<()> {
+        client
+            .query("DELETE token WHERE user_id = $user_id")
+            .bind(("user_id", user_id.to_owned()))
+            .await?;
+        Ok(())
+    }
 
-    // pub async fn get_verification_token_by_user(
-    //     client: &Surreal<Client>,
-    //     user_id: &str,
-    // ) -> Result<Option<Token>> {
-    //     let mut results = client
-    //         .query("SELECT * FROM token WHERE user_id = $user_id AND device_id = $device_id")
-    //         .bind(("user_id", user_id))
-    //         .bind(("device_id", "Verification"))
-    //         .await?;
-
-    //     let token: Option<Token> = results.take(0)?;
-    //     Ok(token)
-    // }
+    // Signed-in devices, each one has a single token row
+    pub async fn get_sessions(client: &Surreal<Any>, user_id: &str) -> Result<Vec<Token>> {
+        let mut results = client
+            .query("SELECT * FROM token WHERE user_id = $user_id")
+            .bind(("user_id", user_id.to_owned()))
+            .await?;
 
-    // pub async fn delete_verification_token_by_user(
-    //     client: &Surreal<Client>,
-    //     user_id: &str,
-    // ) -> Result<()> {
-    //     client
-    //         .query("DELETE token WHERE user_id = $user_id AND device_id = $device_id")
-    //         .bind(("user_id", user_id))
-    //         .bind(("device_id", "Verification"))
-    //         .await?;
-    //     Ok(())
-    // }
+        let tokens: Vec<Token> = results.take(0)?;
+        Ok(tokens)
+    }
+
+    pub async fn delete_by_access_token(client: &Surreal<Any>, access_token: &str) -> Result<()> {
+        client
+            .query("DELETE token WHERE access_token = $access_token")
+            .bind(("access_token", access_token.to_owned()))
+            .await?;
+        Ok(())
+    }
+
+    // Returns whether the user had a session on that device
+    pub async fn delete_by_device(
+        client: &Surreal<Any>,
+        user_id: &str,
+        device_id: &str,
+    ) -> Result<bool> {
+        let mut results = client
+            .query("DELETE token WHERE user_id = $user_id AND device_id = $device_id RETURN BEFORE")
+            .bind(("user_id", user_id.to_owned()))
+            .bind(("device_id", device_id.to_owned()))
+            .await?;
+
+        let deleted: Vec<Token> = results.take(0)?;
+        Ok(!deleted.is_empty())
+    }
+
+    pub async fn delete_other_devices(
+        client: &Surreal<Any>,
+        user_id: &str,
+        device_id: &str,
+    ) -> Result<()> {
+        client
+            .query("DELETE token WHERE user_id = $user_id AND device_id != $device_id")
+            .bind(("user_id", user_id.to_owned()))
+            .bind(("device_id", device_id.to_owned()))
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        model::{
+            Controller,
+            user::{NewUser, UserController},
+        },
+        repo::surreal::SurrealDB,
+    };
+
+    #[actix_web::test]
+    async fn reused_refresh_token_revokes_the_device() {
+        config::init_for_tests();
+        let db = SurrealDB::memory().await.unwrap();
+        let new_user = NewUser {
+            password: Some(String::from("correct horse")),
+            email: Some(String::from("ada@example.com")),
+            avatar_seed: Some(String::from("ada")),
+            ..Default::default()
+        };
+        let user = UserController::create(&db.surreal, &new_user)
+            .await
+            .unwrap();
+        let device_id = "device:phone";
+
+        let first = TokenController::create_or_update(&db.surreal, &user, device_id)
+            .await
+            .unwrap();
+        let first_refresh = first.refresh_token.unwrap();
+
+        TokenManager::validate_refresh_token(&db.surreal, &first_refresh, device_id)
+            .await
+            .unwrap();
+        let second = TokenController::create_or_update(&db.surreal, &user, device_id)
+            .await
+            .unwrap();
+
+        // The exchanged token comes back, whoever sends it may have stolen it
+        let replayed =
+            TokenManager::validate_refresh_token(&db.surreal, &first_refresh, device_id).await;
+        assert!(matches!(replayed, Err(Error::TokenMismatch)));
+
+        let session = TokenController::get_by_device(&db.surreal, device_id.to_string())
+            .await
+            .unwrap();
+        assert!(session.is_none());
+
+        let current = TokenManager::validate_refresh_token(
+            &db.surreal,
+            &second.refresh_token.unwrap(),
+            device_id,
+        )
+        .await;
+        assert!(current.is_err());
+    }
 }
@@ -1,9 +1,11 @@
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use surrealdb::{Datetime, Surreal, engine::remote::ws::Client};
+use surrealdb::{Datetime, Surreal, engine::any::Any};
 use surrealitos::{Relation, SurrealId, extract_id, serialize_as_optional_record};
 
 use crate::{
+    api::{SortDirection, deserialize_optional_bool},
     error::{Error, Result},
     model::{Controller, LLMProvider, user::User},
 };
@@ -25,6 +27,47 @@ pub enum Status {
     Fail,
 }
 
+// Ordered the way the pipeline runs them
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub enum Step {
+    Transcribe,
+    Diarize,
+    Summarize,
+}
+
+impl Step {
+    // Status a transcription shows while this step runs
+    pub fn status(&self) -> Status {
+        match self {
+            Step::Transcribe => Status::Transcribing,
+            Step::Diarize => Status::Diarizing,
+            Step::Summarize => Status::Summarizing,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Failure {
+    pub step: Step,
+    pub code: String,
+    pub message: String,
+}
+
+impl Failure {
+    pub fn new(step: Step, code: &str, message: &str) -> Self {
+        Failure {
+            step,
+            code: code.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn from_error(step: Step, error: &Error) -> Self {
+        Self::new(step, error.code(), &error.to_string())
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all(serialize = "camelCase"))]
 pub struct Transcription {
@@ -40,6 +83,9 @@ pub struct Transcription {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub language: Option<String>,
 
+    #[serde(skip_serializing)]
+    pub segments: Option<Vec<Segment>>, // Raw Mistral segments, input for diarization
+
     #[serde(skip_serializing_if = "Option::is_none")]
     pub diarized: Option<Vec<Segment>>,
 
@@ -54,6 +100,9 @@ pub struct Transcription {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub audio_file: Option<String>, // B2 url
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failure: Option<Failure>, // Set when status is Fail
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, Default)]
@@ -93,21 +142,209 @@ pub struct TranscriptionPatch {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub raw: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub segments: Option<Vec<Segment>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub diarized: Option<Vec<Segment>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub note: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub language: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub llm: Option<String>,
+    #[serde(alias = "llmProvider", skip_serializing_if = "Option::is_none")]
+    pub llm_provider: Option<LLMProvider>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failure: Option<Failure>,
 
     #[serde(skip_deserializing)]
     pub updated_at: Option<Datetime>,
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TranscriptionSort {
+    #[default]
+    #[serde(alias = "createdAt")]
+    CreatedAt,
+    #[serde(alias = "updatedAt")]
+    UpdatedAt,
+    Status,
+    Language,
+}
+
+impl TranscriptionSort {
+    pub fn field(&self) -> &'static str {
+        match self {
+            TranscriptionSort::CreatedAt => "created_at",
+            TranscriptionSort::UpdatedAt => "updated_at",
+            TranscriptionSort::Status => "status",
+            TranscriptionSort::Language => "language",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+pub struct TranscriptionFilters {
+    pub status: Option<Status>,
+    pub language: Option<String>,
+    #[serde(alias = "createdAfter")]
+    pub created_after: Option<DateTime<Utc>>,
+    #[serde(alias = "createdBefore")]
+    pub created_before: Option<DateTime<Utc>>,
+    #[serde(
+        alias = "hasNote",
+        default,
+        deserialize_with = "deserialize_optional_bool"
+    )]
+    pub has_note: Option<bool>,
+    #[serde(alias = "sortBy")]
+    pub sort_by: Option<TranscriptionSort>,
+    pub direction: Option<SortDirection>,
+}
+
+impl TranscriptionFilters {
+    // Conditions only reference parameters bound in User::get_transcriptions
+    pub fn conditions(&self) -> Vec<&'static str> {
+        let mut conditions = vec!["user = $user"];
+
+        if self.status.is_some() {
+            conditions.push("status = $status");
+        }
+        if self.language.is_some() {
+            conditions.push("language = $language");
+        }
+        if self.created_after.is_some() {
+            conditions.push("created_at >= $created_after");
+        }
+        if self.created_before.is_some() {
+            conditions.push("created_at <= $created_before");
+        }
+        match self.has_note {
+            Some(true) => conditions.push("note != NONE"),
+            Some(false) => conditions.push("note = NONE"),
+            None => {}
+        }
+
+        conditions
+    }
+
+    pub fn order(&self) -> String {
+        format!(
+            "{} {}",
+            self.sort_by.clone().unwrap_or_default().field(),
+            self.direction.clone().unwrap_or_default().keyword()
+        )
+    }
+}
+
+const HIGHLIGHT_OPEN: &str = "<mark>";
+const HIGHLIGHT_CLOSE: &str = "</mark>";
+const SNIPPET_RADIUS: usize = 80;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SearchHit {
+    pub id: SurrealId,
+    pub status: Status,
+    pub created_at: String,
+    pub updated_at: String,
+    pub language: Option<String>,
+    pub score: Option<f64>,
+    pub raw_highlight: Option<String>,
+    pub note_highlight: Option<String>,
+    pub diarized: Option<Vec<Segment>>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Snippet {
+    pub field: String,
+    pub text: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchResult {
+    pub id: SurrealId,
+    pub status: Status,
+    pub created_at: String,
+    pub updated_at: String,
+    pub language: Option<String>,
+    pub score: f64,
+    pub snippets: Vec<Snippet>,
+    pub segments: Vec<Segment>, // Matching segments, start and end let the client seek the audio
+}
+
+// Cuts the highlighted text down to the first match and some context around it
+fn snippet(highlighted: &str) -> Option<String> {
+    let start = highlighted.find(HIGHLIGHT_OPEN)?;
+    let end = highlighted[start..]
+        .find(HIGHLIGHT_CLOSE)
+        .map(|position| start + position + HIGHLIGHT_CLOSE.len())
+        .unwrap_or(highlighted.len());
+
+    let mut from = start.saturating_sub(SNIPPET_RADIUS);
+    while !highlighted.is_char_boundary(from) {
+        from -= 1;
+    }
+
+    let mut to = (end + SNIPPET_RADIUS).min(highlighted.len());
+    while !highlighted.is_char_boundary(to) {
+        to += 1;
+    }
+
+    let prefix = if from > 0 { "…" } else { "" };
+    let suffix = if to < highlighted.len() { "…" } else { "" };
+
+    Some(format!("{prefix}{}{suffix}", highlighted[from..to].trim()))
+}
+
+impl SearchHit {
+    pub fn into_result(self, query: &str) -> SearchResult {
+        let terms: Vec<String> = query
+            .split_whitespace()
+            .map(|term| term.to_lowercase())
+            .collect();
+
+        let snippets = [("raw", &self.raw_highlight), ("note", &self.note_highlight)]
+            .into_iter()
+            .filter_map(|(field, highlighted)| {
+                highlighted
+                    .as_deref()
+                    .and_then(snippet)
+                    .map(|text| Snippet {
+                        field: field.to_string(),
+                        text,
+                    })
+            })
+            .collect();
+
+        let segments = self
+            .diarized
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|segment| {
+                let text = segment.text.to_lowercase();
+                terms.iter().any(|term| text.contains(term.as_str()))
+            })
+            .collect();
+
+        SearchResult {
+            id: self.id,
+            status: self.status,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+            language: self.language,
+            score: self.score.unwrap_or(0.0),
+            snippets,
+            segments,
+        }
+    }
+}
+
 pub struct TranscriptionController;
 
 #[async_trait]
 impl Controller<Transcription, NewTranscription, TranscriptionPatch> for TranscriptionController {
-    async fn get(client: &Surreal<Client>, id: &SurrealId) -> Result<Option<Transcription>> {
+    async fn get(client: &Surreal<Any>, id: &SurrealId) -> Result<Option<Transcription>> {
         let mut results = client
             .query("SELECT * FROM ONLY $transcription")
             .bind(("transcription", id.clone().0))
@@ -118,7 +355,7 @@ impl Controller<Transcription, NewTranscription, TranscriptionPatch> for Transcr
     }
 
     async fn update(
-        client: &Surreal<Client>,
+        client: &Surreal<Any>,
         id: &str,
         transcription_patch: &TranscriptionPatch,
     ) -> Result<Transcription> {
@@ -137,7 +374,7 @@ impl Controller<Transcription, NewTranscription, TranscriptionPatch> for Transcr
     }
 
     async fn create(
-        client: &Surreal<Client>,
+        client: &Surreal<Any>,
         new_transcription: &NewTranscription,
     ) -> Result<Transcription> {
         let mut transcription_data = new_transcription.clone();
@@ -151,9 +388,10 @@ impl Controller<Transcription, NewTranscription, TranscriptionPatch> for Transcr
         transcription.ok_or(Error::StoreData("transcription".to_string()))
     }
 
-    async fn delete(client: &Surreal<Client>, id: &SurrealId) -> Result<()> {
-        // Use backblaze connector to delete the audio file from the url
+    async fn delete(client: &Surreal<Any>, id: &SurrealId) -> Result<()> {
+        // The audio file is removed from Backblaze by the caller
         client
+            .query("DELETE job WHERE transcription = $id RETURN NONE")
             .query("DELETE transcription WHERE id = $id RETURN NONE")
             .bind(("id", id.clone().0))
             .await?;
@@ -161,3 +399,55 @@ impl Controller<Transcription, NewTranscription, TranscriptionPatch> for Transcr
         Ok(())
     }
 }
+
+impl TranscriptionController {
+    pub async fn get_owned(
+        client: &Surreal<Any>,
+        id: &SurrealId,
+        user_id: &SurrealId,
+    ) -> Result<Option<Transcription>> {
+        let mut results = client
+            .query("SELECT * FROM $transcription WHERE user = $user")
+            .bind(("transcription", id.clone().0))
+            .bind(("user", user_id.clone().0))
+            .await?;
+
+        let transcription: Option<Transcription> = results.take(0)?;
+        Ok(transcription)
+    }
+
+    // Moves the transcription back into a running step and forgets the previous failure
+    pub async fn restart(
+        client: &Surreal<Any>,
+        id: &SurrealId,
+        step: &Step,
+    ) -> Result<Transcription> {
+        let mut results = client
+            .query("UPDATE ONLY $transcription SET status = $status, failure = NONE, updated_at = time::now()")
+            .bind(("transcription", id.clone().0))
+            .bind(("status", step.status()))
+            .await?;
+
+        let transcription: Option<Transcription> = results.take(0)?;
+        transcription.ok_or(Error::StoreData("transcription".to_string()))
+    }
+
+    pub async fn search(
+        client: &Surreal<Any>,
+        user_id: &SurrealId,
+        query: &str,
+        limit: usize,
+        offset: usize,
+    ) -> Result<Vec<SearchHit>> {
+        let mut results = client
+            .query("SELECT id, status, created_at, updated_at, language, diarized, search::highlight('<mark>', '</mark>', 0) AS raw_highlight, search::highlight('<mark>', '</mark>', 1) AS note_highlight, (search::score(0) OR 0) + (search::score(1) OR 0) + (search::score(2) OR 0) AS score FROM transcription WHERE user = $user AND (raw @0@ $query OR note @1@ $query OR diarized.*.text @2@ $query) ORDER BY score DESC LIMIT $limit START $offset")
+            .bind(("user", user_id.clone().0))
+            .bind(("query", query.to_owned()))
+            .bind(("limit", limit))
+            .bind(("offset", offset))
+            .await?;
+
+        let hits: Vec<SearchHit> = results.take(0)?;
+        Ok(hits)
+    }
+}
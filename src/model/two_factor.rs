@@ -0,0 +1,422 @@
+use chacha20poly1305::{
+    XChaCha20Poly1305,
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+};
+use chrono::Utc;
+use rand::{
+    Rng,
+    distributions::{Alphanumeric, DistString},
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use surrealdb::{Surreal, engine::any::Any, sql::Datetime};
+use surrealitos::SurrealId;
+use totp_rs::{Algorithm, TOTP};
+
+use crate::{
+    config,
+    error::{Error, Result},
+    model::{one_time_token::hash_token, user::User},
+};
+
+const ISSUER: &str = "Echo";
+const RECOVERY_CODE_COUNT: usize = 10;
+const STEP: u64 = 30;
+
+// Only enabled once the user proves their authenticator works with a first code
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TwoFactor {
+    pub id: SurrealId,
+    pub user_id: String,
+    pub secret: String, // Hex encoded XChaCha20 ciphertext of the TOTP secret
+    pub nonce: String,
+    pub enabled: bool,
+    #[serde(default)]
+    pub recovery_codes: Vec<String>, // Hashes, each one works once
+    pub last_time_step: Option<u64>, // A code is only accepted for a later step than this
+    pub created_at: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct NewTwoFactor {
+    user_id: String,
+    secret: String,
+    nonce: String,
+    enabled: bool,
+    recovery_codes: Vec<String>,
+    created_at: Datetime,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct TwoFactorSetup {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+// Secrets are encrypted with a key of their own, not stored next to them
+fn cipher() -> XChaCha20Poly1305 {
+    let secret = &config::get().two_factor_secret;
+    let key = Sha256::digest(format!("two-factor:{secret}").as_bytes());
+    XChaCha20Poly1305::new(&key)
+}
+
+fn encrypt_secret(secret: &[u8]) -> (String, String) {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let encrypted = cipher().encrypt(&nonce, secret).expect("Encryption error");
+
+    (hex::encode(encrypted), hex::encode(nonce))
+}
+
+fn decrypt_secret(two_factor: &TwoFactor) -> Result<Vec<u8>> {
+    let encrypted = hex::decode(&two_factor.secret).map_err(|_| Error::TokenMismatch)?;
+    let nonce = hex::decode(&two_factor.nonce).map_err(|_| Error::TokenMismatch)?;
+
+    cipher()
+        .decrypt(nonce.as_slice().into(), encrypted.as_ref())
+        .map_err(|_| Error::TokenMismatch)
+}
+
+fn totp(secret: Vec<u8>, user: &User) -> TOTP {
+    let account = user.email.clone().unwrap_or(user.id.to_string());
+
+    // What authenticator apps expect, with one step of skew for clock drift
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        STEP,
+        secret,
+        Some(ISSUER.to_string()),
+        account.replace(':', ""),
+    )
+    .expect("Valid TOTP parameters")
+}
+
+// The time step the code was generated for, if it is within the allowed skew
+fn matching_time_step(totp: &TOTP, code: &str, time: u64) -> Option<u64> {
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    let current = time / STEP;
+
+    [current, current.saturating_sub(1), current + 1]
+        .into_iter()
+        .find(|step| totp.generate(step * STEP) == code)
+}
+
+fn check_code(two_factor: &TwoFactor, user: &User, code: &str) -> Result<Option<u64>> {
+    let secret = decrypt_secret(two_factor)?;
+    let now = Utc::now().timestamp() as u64;
+
+    Ok(matching_time_step(&totp(secret, user), code, now))
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.trim().to_lowercase()
+}
+
+// Returns the plaintext codes and the hashes that get stored
+fn generate_recovery_codes() -> (Vec<String>, Vec<String>) {
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code = Alphanumeric
+                .sample_string(&mut rand::thread_rng(), 10)
+                .to_lowercase();
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect();
+    let hashes = codes.iter().map(|code| hash_token(code)).collect();
+
+    (codes, hashes)
+}
+
+pub struct TwoFactorController;
+
+impl TwoFactorController {
+    pub async fn get_by_user(client: &Surreal<Any>, user_id: &str) -> Result<Option<TwoFactor>> {
+        let mut results = client
+            .query("SELECT * FROM two_factor WHERE user_id = $user_id")
+            .bind(("user_id", user_id.to_owned()))
+            .await?;
+
+        let two_factor: Option<TwoFactor> = results.take(0)?;
+        Ok(two_factor)
+    }
+
+    pub async fn is_enabled(client: &Surreal<Any>, user_id: &str) -> Result<bool> {
+        let two_factor = Self::get_by_user(client, user_id).await?;
+        Ok(two_factor.is_some_and(|two_factor| two_factor.enabled))
+    }
+
+    // Starts over with a new secret until the user confirms one
+    pub async fn setup(client: &Surreal<Any>, user: &User) -> Result<TwoFactorSetup> {
+        let user_id = user.id.to_string();
+        if Self::is_enabled(client, &user_id).await? {
+            return Err(Error::BadRequest(
+                "Two-factor authentication is already enabled".to_string(),
+            ));
+        }
+
+        let mut secret = [0u8; 20];
+        rand::thread_rng().fill(&mut secret);
+        let (encrypted, nonce) = encrypt_secret(&secret);
+
+        let new_two_factor = NewTwoFactor {
+            user_id: user_id.clone(),
+            secret: encrypted,
+            nonce,
+            enabled: false,
+            recovery_codes: Vec::new(),
+            created_at: Datetime::default(),
+        };
+
+        Self::delete_by_user(client, &user_id).await?;
+        let stored: Option<TwoFactor> = client.create("two_factor").content(new_two_factor).await?;
+        stored.ok_or(Error::StoreData("two_factor".to_string()))?;
+
+        let totp = totp(secret.to_vec(), user);
+
+        Ok(TwoFactorSetup {
+            secret: totp.get_secret_base32(),
+            otpauth_uri: totp.get_url(),
+        })
+    }
+
+    // The first valid code turns 2FA on and hands out the recovery codes
+    pub async fn confirm(client: &Surreal<Any>, user: &User, code: &str) -> Result<Vec<String>> {
+        let two_factor = Self::get_by_user(client, &user.id.to_string())
+            .await?
+            .ok_or(Error::BadRequest(
+                "Two-factor authentication has not been set up".to_string(),
+            ))?;
+
+        if two_factor.enabled {
+            return Err(Error::BadRequest(
+                "Two-factor authentication is already enabled".to_string(),
+            ));
+        }
+
+        let step = check_code(&two_factor, user, code)?.ok_or(Error::WrongCredentials)?;
+
+        let (codes, hashes) = generate_recovery_codes();
+        let mut results = client
+            .query("UPDATE $two_factor SET enabled = true, recovery_codes = $recovery_codes, last_time_step = $step WHERE enabled = false")
+            .bind(("two_factor", two_factor.id.0))
+            .bind(("recovery_codes", hashes))
+            .bind(("step", step))
+            .await?;
+
+        let confirmed: Vec<TwoFactor> = results.take(0)?;
+        if confirmed.is_empty() {
+            return Err(Error::WrongCredentials);
+        }
+
+        Ok(codes)
+    }
+
+    // Accepts an authenticator code or burns one of the recovery codes
+    pub async fn verify(client: &Surreal<Any>, user: &User, code: &str) -> Result<()> {
+        let two_factor = Self::get_by_user(client, &user.id.to_string())
+            .await?
+            .filter(|two_factor| two_factor.enabled)
+            .ok_or(Error::WrongCredentials)?;
+
+        let accepted = match check_code(&two_factor, user, code)? {
+            Some(step) => Self::use_time_step(client, &two_factor.id, step).await?,
+            None => Self::use_recovery_code(client, &two_factor.id, code).await?,
+        };
+
+        if !accepted {
+            return Err(Error::WrongCredentials);
+        }
+
+        Ok(())
+    }
+
+    // Moves the last step forward in the same statement that checks it, so a code can't be
+    // replayed while it is still valid
+    async fn use_time_step(client: &Surreal<Any>, id: &SurrealId, step: u64) -> Result<bool> {
+        let mut results = client
+            .query("UPDATE $two_factor SET last_time_step = $step WHERE last_time_step IS NONE OR last_time_step < $step")
+            .bind(("two_factor", id.clone().0))
+            .bind(("step", step))
+            .await?;
+
+        let used: Vec<TwoFactor> = results.take(0)?;
+        Ok(!used.is_empty())
+    }
+
+    async fn use_recovery_code(client: &Surreal<Any>, id: &SurrealId, code: &str) -> Result<bool> {
+        let mut results = client
+            .query("UPDATE $two_factor SET recovery_codes -= $code_hash WHERE recovery_codes CONTAINS $code_hash")
+            .bind(("two_factor", id.clone().0))
+            .bind(("code_hash", hash_token(&normalize_recovery_code(code))))
+            .await?;
+
+        let used: Vec<TwoFactor> = results.take(0)?;
+        Ok(!used.is_empty())
+    }
+
+    pub async fn regenerate_recovery_codes(
+        client: &Surreal<Any>,
+        user_id: &str,
+    ) -> Result<Vec<String>> {
+        let (codes, hashes) = generate_recovery_codes();
+
+        let mut results = client
+            .query("UPDATE two_factor SET recovery_codes = $recovery_codes WHERE user_id = $user_id AND enabled = true")
+            .bind(("user_id", user_id.to_owned()))
+            .bind(("recovery_codes", hashes))
+            .await?;
+
+        let updated: Vec<TwoFactor> = results.take(0)?;
+        if updated.is_empty() {
+            return Err(Error::BadRequest(
+                "Two-factor authentication is not enabled".to_string(),
+            ));
+        }
+
+        Ok(codes)
+    }
+
+    pub async fn delete_by_user(client: &Surreal<Any>, user_id: &str) -> Result<()> {
+        client
+            .query("DELETE two_factor WHERE user_id = $user_id")
+            .bind(("user_id", user_id.to_owned()))
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::surreal::SurrealDB;
+
+    fn test_totp() -> TOTP {
+        TOTP::new(
+            Algorithm::SHA1,
+            6,
+            1,
+            STEP,
+            b"12345678901234567890".to_vec(),
+            Some(ISSUER.to_string()),
+            String::from("ada@example.com"),
+        )
+        .unwrap()
+    }
+
+    async fn stored_two_factor(db: &SurrealDB, recovery_codes: Vec<String>) -> TwoFactor {
+        let new_two_factor = NewTwoFactor {
+            user_id: String::from("user:ada"),
+            secret: String::new(),
+            nonce: String::new(),
+            enabled: true,
+            recovery_codes,
+            created_at: Datetime::default(),
+        };
+
+        let stored: Option<TwoFactor> = db
+            .surreal
+            .create("two_factor")
+            .content(new_two_factor)
+            .await
+            .unwrap();
+        stored.unwrap()
+    }
+
+    #[test]
+    fn matches_codes_within_one_step_of_skew() {
+        let totp = test_totp();
+        let time = 1_700_000_000;
+        let step = time / STEP;
+
+        let current = totp.generate(time);
+        assert_eq!(matching_time_step(&totp, &current, time), Some(step));
+
+        let spaced = format!("{} {}", &current[..3], &current[3..]);
+        assert_eq!(matching_time_step(&totp, &spaced, time), Some(step));
+
+        let previous = totp.generate(time - STEP);
+        assert_eq!(matching_time_step(&totp, &previous, time), Some(step - 1));
+
+        let next = totp.generate(time + STEP);
+        assert_eq!(matching_time_step(&totp, &next, time), Some(step + 1));
+    }
+
+    #[test]
+    fn rejects_codes_outside_the_skew() {
+        let totp = test_totp();
+        let time = 1_700_000_000;
+
+        let stale = totp.generate(time - 3 * STEP);
+        assert_eq!(matching_time_step(&totp, &stale, time), None);
+        assert_eq!(matching_time_step(&totp, "not a code", time), None);
+    }
+
+    #[actix_web::test]
+    async fn time_steps_are_only_accepted_once() {
+        let db = SurrealDB::memory().await.unwrap();
+        let two_factor = stored_two_factor(&db, Vec::new()).await;
+        let id = &two_factor.id;
+
+        assert!(
+            TwoFactorController::use_time_step(&db.surreal, id, 10)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !TwoFactorController::use_time_step(&db.surreal, id, 10)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !TwoFactorController::use_time_step(&db.surreal, id, 9)
+                .await
+                .unwrap()
+        );
+        assert!(
+            TwoFactorController::use_time_step(&db.surreal, id, 11)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[actix_web::test]
+    async fn recovery_codes_work_once() {
+        let db = SurrealDB::memory().await.unwrap();
+        let (codes, hashes) = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+
+        let two_factor = stored_two_factor(&db, hashes).await;
+        let id = &two_factor.id;
+
+        // Typed back however the user copied it
+        let typed = format!("  {}  ", codes[0].to_uppercase());
+        assert!(
+            TwoFactorController::use_recovery_code(&db.surreal, id, &typed)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !TwoFactorController::use_recovery_code(&db.surreal, id, &codes[0])
+                .await
+                .unwrap()
+        );
+        assert!(
+            TwoFactorController::use_recovery_code(&db.surreal, id, &codes[1])
+                .await
+                .unwrap()
+        );
+        assert!(
+            !TwoFactorController::use_recovery_code(&db.surreal, id, "abcde-fghij")
+                .await
+                .unwrap()
+        );
+
+        let stored = TwoFactorController::get_by_user(&db.surreal, "user:ada")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.recovery_codes.len(), RECOVERY_CODE_COUNT - 2);
+    }
+}
@@ -7,13 +7,17 @@ use argon2::{
 use async_trait::async_trait;
 use rand::distributions::{Alphanumeric, DistString};
 use serde::{Deserialize, Serialize};
-use surrealdb::{Surreal, engine::remote::ws::Client, sql::Datetime};
+use surrealdb::{Surreal, engine::any::Any, sql::Datetime};
 use surrealitos::{SurrealId, extract_id};
 
 use crate::{
     api::PaginationParameters,
     error::{Error, Result},
-    model::{Controller, transcription::Transcription},
+    model::{
+        Controller, LLMProvider,
+        role::Role,
+        transcription::{Transcription, TranscriptionFilters},
+    },
 };
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Default)]
@@ -38,6 +42,10 @@ pub struct User {
     pub name: Option<String>,
     pub verified_email: Option<bool>,
     pub blaze_token: Option<String>,
+    pub llm_provider: Option<LLMProvider>,
+    pub llm_model: Option<String>,
+    #[serde(default)]
+    pub roles: Vec<Role>, // Granted roles, the base one comes from user_type
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
@@ -63,18 +71,30 @@ pub struct NewUser {
 
 #[derive(Serialize, Deserialize, Default, Clone, Debug)]
 pub struct UserPatch {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
     #[serde(alias = "avatarSeed", skip_serializing_if = "Option::is_none")]
     pub avatar_seed: Option<String>,
-    #[serde(alias = "verifiedEmail", skip_serializing_if = "Option::is_none")]
+    // Only set by the server once the emailed token comes back
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
     pub verified_email: Option<bool>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    // Only set by the server, clients go through PUT /api/user/password
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
     pub password_hash: Option<String>,
+    #[serde(alias = "llmProvider", skip_serializing_if = "Option::is_none")]
+    pub llm_provider: Option<LLMProvider>,
+    #[serde(alias = "llmModel", skip_serializing_if = "Option::is_none")]
+    pub llm_model: Option<String>,
 
     #[serde(skip_deserializing)]
     pub updated_at: Option<Datetime>,
 }
 
+// Emails are stored and looked up trimmed and lowercased, so casing never splits an account
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
 pub struct PasswordHasher<'a> {
     argon2: Argon2<'a>,
 }
@@ -110,20 +130,44 @@ impl PasswordHasher<'_> {
 }
 
 impl User {
+    pub fn permissions(&self) -> Vec<String> {
+        let mut permissions: Vec<String> = Vec::new();
+        let base_role = Role::from(&self.user_type);
+
+        for role in std::iter::once(&base_role).chain(self.roles.iter()) {
+            for permission in role.permissions() {
+                if !permissions.iter().any(|existing| existing == permission) {
+                    permissions.push(permission.to_string());
+                }
+            }
+        }
+
+        permissions
+    }
+
     pub async fn get_transcriptions(
         &self,
-        client: &Surreal<Client>,
-        pagination: &PaginationParameters,
-    ) -> Result<Vec<Transcription>> {
+        client: &Surreal<Any>,
+        pagination: &PaginationParameters<TranscriptionFilters>,
+    ) -> Result<(Vec<Transcription>, usize)> {
+        let filters = pagination.filters.clone().unwrap_or_default();
+        let conditions = filters.conditions().join(" AND ");
+
         let mut results = client
-            .query("SELECT id, status, created_at, updated_at, user, language FROM transcription WHERE user = $user ORDER BY created_at LIMIT $limit START $offset")
+            .query(format!("SELECT id, status, created_at, updated_at, user, language FROM transcription WHERE {conditions} ORDER BY {} LIMIT $limit START $offset", filters.order()))
+            .query(format!("SELECT count() AS total FROM transcription WHERE {conditions} GROUP ALL"))
             .bind(("user", self.id.clone().0))
+            .bind(("status", filters.status))
+            .bind(("language", filters.language))
+            .bind(("created_after", filters.created_after.map(Datetime::from)))
+            .bind(("created_before", filters.created_before.map(Datetime::from)))
             .bind(("limit", pagination.limit))
             .bind(("offset", pagination.offset))
             .await?;
 
         let transcriptions: Vec<Transcription> = results.take(0)?;
-        Ok(transcriptions)
+        let total: Option<usize> = results.take((1, "total"))?;
+        Ok((transcriptions, total.unwrap_or(0)))
     }
 }
 
@@ -131,7 +175,7 @@ pub struct UserController;
 
 #[async_trait]
 impl Controller<User, NewUser, UserPatch> for UserController {
-    async fn get(client: &Surreal<Client>, id: &SurrealId) -> Result<Option<User>> {
+    async fn get(client: &Surreal<Any>, id: &SurrealId) -> Result<Option<User>> {
         let mut results = client
             .query("SELECT * FROM ONLY $user")
             .bind(("user", id.clone().0))
@@ -141,7 +185,7 @@ impl Controller<User, NewUser, UserPatch> for UserController {
         Ok(user)
     }
 
-    async fn create(client: &Surreal<Client>, new_user: &NewUser) -> Result<User> {
+    async fn create(client: &Surreal<Any>, new_user: &NewUser) -> Result<User> {
         let password_hasher = PasswordHasher::new();
         let mut user_data = new_user.clone();
 
@@ -155,12 +199,13 @@ impl Controller<User, NewUser, UserPatch> for UserController {
         user_data.created_at = Some(Datetime::default());
         user_data.updated_at = Some(Datetime::default());
         user_data.user_type = Some(UserType::User);
+        user_data.email = user_data.email.as_deref().map(normalize_email);
 
         let user: Option<User> = client.create("user").content(user_data).await?;
         user.ok_or(Error::StoreData("user".to_string()))
     }
 
-    async fn update(client: &Surreal<Client>, id: &str, user_patch: &UserPatch) -> Result<User> {
+    async fn update(client: &Surreal<Any>, id: &str, user_patch: &UserPatch) -> Result<User> {
         let user_id = extract_id(id, "user");
         let mut user_data = user_patch.clone();
 
@@ -175,29 +220,32 @@ impl Controller<User, NewUser, UserPatch> for UserController {
         Ok(user)
     }
 
-    async fn delete(client: &Surreal<Client>, id: &SurrealId) -> Result<()> {
+    async fn delete(client: &Surreal<Any>, id: &SurrealId) -> Result<()> {
         client
             .query("DELETE device WHERE user_id = $user")
             .query("DELETE token WHERE user_id = $user")
+            .query("DELETE api_key WHERE user_id = $user_id")
+            .query("DELETE two_factor WHERE user_id = $user_id")
             .query("DELETE $user")
             .bind(("user", id.0.clone()))
+            .bind(("user_id", id.to_string()))
             .await?;
         Ok(())
     }
 }
 
 impl UserController {
-    pub async fn get_by_email(client: &Surreal<Client>, email: &str) -> Result<Option<User>> {
+    pub async fn get_by_email(client: &Surreal<Any>, email: &str) -> Result<Option<User>> {
         let mut results = client
             .query("SELECT * FROM user WHERE email = $email")
-            .bind(("email", email.to_owned()))
+            .bind(("email", normalize_email(email)))
             .await?;
 
         let user: Option<User> = results.take(0)?;
         Ok(user)
     }
 
-    pub async fn create_guest(client: &Surreal<Client>) -> Result<User> {
+    pub async fn create_guest(client: &Surreal<Any>) -> Result<User> {
         // let config = ConfigController::create_default(client).await?;
         let guest_discriminator = Alphanumeric.sample_string(&mut rand::thread_rng(), 4);
 
@@ -213,4 +261,57 @@ impl UserController {
         let user: Option<User> = client.create("user").content(new_guest).await?;
         user.ok_or(Error::StoreData("user".to_string()))
     }
+
+    pub async fn list(
+        client: &Surreal<Any>,
+        pagination: &PaginationParameters,
+    ) -> Result<(Vec<User>, usize)> {
+        let mut results = client
+            .query("SELECT * FROM user ORDER BY created_at DESC LIMIT $limit START $offset")
+            .query("SELECT count() AS total FROM user GROUP ALL")
+            .bind(("limit", pagination.limit))
+            .bind(("offset", pagination.offset))
+            .await?;
+
+        let users: Vec<User> = results.take(0)?;
+        let total: Option<usize> = results.take((1, "total"))?;
+        Ok((users, total.unwrap_or(0)))
+    }
+
+    pub async fn set_roles(
+        client: &Surreal<Any>,
+        id: &SurrealId,
+        roles: Vec<Role>,
+    ) -> Result<User> {
+        let mut results = client
+            .query("UPDATE ONLY $user SET roles = $roles, updated_at = time::now()")
+            .bind(("user", id.clone().0))
+            .bind(("roles", roles))
+            .await?;
+
+        let user: Option<User> = results.take(0)?;
+        user.ok_or(Error::NotFound("user".to_string()))
+    }
+
+    // Keeps the record id so everything the guest created stays theirs
+    pub async fn upgrade_guest(
+        client: &Surreal<Any>,
+        id: &SurrealId,
+        email: &str,
+        password_hash: &str,
+        name: Option<String>,
+    ) -> Result<User> {
+        let mut results = client
+            .query("UPDATE $user SET user_type = 'User', email = $email, password_hash = $password_hash, verified_email = false, name = $name ?? name, updated_at = time::now() WHERE user_type = 'Guest'")
+            .bind(("user", id.clone().0))
+            .bind(("email", normalize_email(email)))
+            .bind(("password_hash", password_hash.to_owned()))
+            .bind(("name", name))
+            .await?;
+
+        let users: Vec<User> = results.take(0)?;
+        users.into_iter().next().ok_or(Error::BadRequest(
+            "Only guest accounts can be upgraded".to_string(),
+        ))
+    }
 }
@@ -0,0 +1,144 @@
+use surrealdb::{Surreal, engine::any::Any};
+use surrealitos::SurrealId;
+
+use crate::{
+    api::make_default_webhook_url,
+    connector::{
+        HttpMethod,
+        backblaze::BackBlaze,
+        llm::{self, summary},
+        mistral::Mistral,
+        modal::{BaseParameters, DiarizationInput, ModalAI, ToolAsyncIO},
+        reverb::Reverb,
+    },
+    error::{Error, Result},
+    model::{
+        Controller,
+        job::{Job, JobController},
+        transcription::{
+            Failure, Status, Step, Transcription, TranscriptionController, TranscriptionPatch,
+        },
+    },
+};
+
+pub mod worker;
+
+pub enum Outcome {
+    Done,
+    // The step continues outside of the server, the call id identifies it on Modal
+    Waiting(String),
+}
+
+async fn load(client: &Surreal<Any>, transcription_id: &SurrealId) -> Result<Transcription> {
+    TranscriptionController::get(client, transcription_id)
+        .await?
+        .ok_or(Error::NotFound("transcription".to_string()))
+}
+
+async fn update(
+    client: &Surreal<Any>,
+    transcription_id: &SurrealId,
+    patch: &TranscriptionPatch,
+) -> Result<Transcription> {
+    let reverb = Reverb::new();
+    let transcription =
+        TranscriptionController::update(client, &transcription_id.to_string(), patch).await?;
+
+    let _ = reverb
+        .notify_update("transcription/updated", transcription.clone())
+        .await;
+
+    Ok(transcription)
+}
+
+pub async fn fail_transcription(
+    client: &Surreal<Any>,
+    transcription_id: &SurrealId,
+    failure: Failure,
+) -> Result<Transcription> {
+    let patch = TranscriptionPatch {
+        status: Some(Status::Fail),
+        failure: Some(failure),
+        ..Default::default()
+    };
+
+    update(client, transcription_id, &patch).await
+}
+
+async fn transcribe(client: &Surreal<Any>, transcription_id: &SurrealId) -> Result<Outcome> {
+    let transcription = load(client, transcription_id).await?;
+    let audio_file = transcription.audio_file.ok_or(Error::BadRequest(
+        "Transcription has no audio file".to_string(),
+    ))?;
+    let file_url = BackBlaze::authorize_url(&audio_file).await?;
+
+    let mistral = Mistral::new();
+    let raw_transcription = mistral.transcribe(&file_url, true).await?;
+
+    let patch = TranscriptionPatch {
+        raw: Some(raw_transcription.text),
+        language: raw_transcription.language,
+        segments: Some(raw_transcription.segments.unwrap_or_default()),
+        status: Some(Status::Diarizing),
+        ..Default::default()
+    };
+
+    update(client, transcription_id, &patch).await?;
+    JobController::enqueue(client, transcription_id, Step::Diarize).await?;
+
+    Ok(Outcome::Done)
+}
+
+async fn diarize(client: &Surreal<Any>, transcription_id: &SurrealId) -> Result<Outcome> {
+    let transcription = load(client, transcription_id).await?;
+    let audio_file = transcription.audio_file.ok_or(Error::BadRequest(
+        "Transcription has no audio file".to_string(),
+    ))?;
+    let file_url = BackBlaze::authorize_url(&audio_file).await?;
+
+    let diarize_input = DiarizationInput {
+        audio: file_url,
+        segments: transcription.segments.unwrap_or_default(),
+        base: BaseParameters {
+            webhook_url: make_default_webhook_url("diarize"),
+            job_id: transcription_id.to_string(),
+        },
+    };
+
+    let modal = ModalAI::new();
+    let output = modal
+        .run::<DiarizationInput, ToolAsyncIO>(HttpMethod::Post, "diarize/initiate", &diarize_input)
+        .await?;
+
+    Ok(Outcome::Waiting(output.call_id))
+}
+
+async fn summarize(client: &Surreal<Any>, transcription_id: &SurrealId) -> Result<Outcome> {
+    let transcription = load(client, transcription_id).await?;
+    let segments = transcription.diarized.ok_or(Error::BadRequest(
+        "Transcription has not been diarized".to_string(),
+    ))?;
+
+    let llm = llm::select(transcription.llm_provider, transcription.llm)?;
+    let note = summary::summarize(llm.as_ref(), &segments).await?;
+
+    let patch = TranscriptionPatch {
+        status: Some(Status::Done),
+        note: Some(note.to_markdown()),
+        llm: Some(llm.model().to_string()),
+        llm_provider: Some(llm.provider()),
+        ..Default::default()
+    };
+
+    update(client, transcription_id, &patch).await?;
+
+    Ok(Outcome::Done)
+}
+
+pub async fn run_step(client: &Surreal<Any>, job: &Job) -> Result<Outcome> {
+    match job.step {
+        Step::Transcribe => transcribe(client, &job.transcription).await,
+        Step::Diarize => diarize(client, &job.transcription).await,
+        Step::Summarize => summarize(client, &job.transcription).await,
+    }
+}
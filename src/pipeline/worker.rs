@@ -0,0 +1,83 @@
+use std::time::Duration;
+
+use actix_web::rt::time::sleep;
+use surrealdb::{Surreal, engine::any::Any};
+
+use crate::{
+    error::{Error, Result},
+    model::{
+        Controller,
+        job::{Job, JobController, JobPatch, JobStatus},
+        transcription::Failure,
+    },
+    pipeline::{self, Outcome},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+// Claims queued jobs one at a time and runs the matching pipeline step
+pub struct Worker {
+    client: Surreal<Any>,
+}
+
+impl Worker {
+    pub fn new(client: Surreal<Any>) -> Self {
+        Worker { client }
+    }
+
+    pub async fn run(self) {
+        if let Err(error) = JobController::resume_unfinished(&self.client).await {
+            log::error!("Error resuming unfinished jobs: {error}");
+        }
+
+        loop {
+            match JobController::claim_next(&self.client).await {
+                Ok(Some(job)) => {
+                    if let Err(error) = self.process(&job).await {
+                        log::error!("Error updating job {}: {error}", job.id);
+                    }
+                }
+                Ok(None) => sleep(POLL_INTERVAL).await,
+                Err(error) => {
+                    log::error!("Error claiming job: {error}");
+                    sleep(POLL_INTERVAL).await;
+                }
+            }
+        }
+    }
+
+    async fn process(&self, job: &Job) -> Result<()> {
+        let patch = match pipeline::run_step(&self.client, job).await {
+            Ok(Outcome::Done) => JobPatch {
+                status: Some(JobStatus::Done),
+                ..Default::default()
+            },
+            Ok(Outcome::Waiting(call_id)) => {
+                return JobController::mark_waiting(&self.client, job, call_id).await;
+            }
+            Err(error) if job.attempts < job.max_attempts => {
+                JobController::retry_later(&self.client, job, &error).await?;
+                return Ok(());
+            }
+            Err(error) => return self.fail(job, &error).await,
+        };
+
+        JobController::update(&self.client, &job.id.to_string(), &patch).await?;
+        Ok(())
+    }
+
+    async fn fail(&self, job: &Job, error: &Error) -> Result<()> {
+        let patch = JobPatch {
+            status: Some(JobStatus::Failed),
+            last_error: Some(error.to_string()),
+            ..Default::default()
+        };
+
+        JobController::update(&self.client, &job.id.to_string(), &patch).await?;
+
+        let failure = Failure::from_error(job.step.clone(), error);
+        pipeline::fail_transcription(&self.client, &job.transcription, failure).await?;
+
+        Ok(())
+    }
+}
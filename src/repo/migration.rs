@@ -0,0 +1,167 @@
+use surrealdb::{Result, Surreal, engine::any::Any};
+
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    script: &'static str,
+}
+
+// Applied in order, a shipped script is never edited, changes go in a new version
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "search_indexes",
+        script: include_str!("migrations/0001_search_indexes.surql"),
+    },
+    Migration {
+        version: 2,
+        name: "tables_and_indexes",
+        script: include_str!("migrations/0002_tables_and_indexes.surql"),
+    },
+    Migration {
+        version: 3,
+        name: "rotated_refresh_tokens",
+        script: include_str!("migrations/0003_rotated_refresh_tokens.surql"),
+    },
+];
+
+const MIGRATION_TABLE: &str = "
+DEFINE TABLE IF NOT EXISTS migration SCHEMAFULL;
+DEFINE FIELD IF NOT EXISTS version ON migration TYPE int;
+DEFINE FIELD IF NOT EXISTS name ON migration TYPE string;
+DEFINE FIELD IF NOT EXISTS applied_at ON migration TYPE datetime;
+";
+
+pub struct MigrationRunner;
+
+impl MigrationRunner {
+    // Runs every migration that is not recorded yet, each one in its own transaction
+    pub async fn run(client: &Surreal<Any>) -> Result<Vec<&'static Migration>> {
+        client.query(MIGRATION_TABLE).await?.check()?;
+
+        let mut results = client.query("SELECT VALUE version FROM migration").await?;
+        let applied: Vec<u32> = results.take(0)?;
+
+        let mut ran = Vec::new();
+        for migration in MIGRATIONS.iter().filter(|m| !applied.contains(&m.version)) {
+            client
+                .query("BEGIN TRANSACTION")
+                .query(migration.script)
+                .query("CREATE type::thing('migration', $version) SET version = $version, name = $name, applied_at = time::now() RETURN NONE")
+                .query("COMMIT TRANSACTION")
+                .bind(("version", migration.version))
+                .bind(("name", migration.name))
+                .await?
+                .check()?;
+
+            ran.push(migration);
+        }
+
+        Ok(ran)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use surrealdb::{
+        engine::any::{self, Any},
+        sql::Thing,
+    };
+
+    use super::*;
+    use crate::repo::surreal::SurrealDB;
+
+    async fn empty_database() -> Surreal<Any> {
+        let client = any::connect("mem://").await.unwrap();
+        client.use_ns("echo").use_db("test").await.unwrap();
+        client
+    }
+
+    #[actix_web::test]
+    async fn guests_without_email_pass_the_unique_index() {
+        let db = SurrealDB::memory().await.unwrap();
+
+        for _ in 0..2 {
+            db.surreal
+                .query("CREATE user SET user_type = 'Guest'")
+                .await
+                .unwrap()
+                .check()
+                .unwrap();
+        }
+
+        let mut results = db.surreal.query("SELECT VALUE id FROM user").await.unwrap();
+        let ids: Vec<Thing> = results.take(0).unwrap();
+        assert_eq!(ids.len(), 2);
+
+        let duplicate = db
+            .surreal
+            .query("CREATE user SET user_type = 'User', email = 'ada@example.com'")
+            .query("CREATE user SET user_type = 'User', email = 'ada@example.com'")
+            .await
+            .unwrap()
+            .check();
+        assert!(duplicate.is_err());
+    }
+
+    #[actix_web::test]
+    async fn lowercases_existing_emails() {
+        let client = empty_database().await;
+        client
+            .query("CREATE user SET email = ' Ada@Example.com '")
+            .await
+            .unwrap()
+            .check()
+            .unwrap();
+
+        MigrationRunner::run(&client).await.unwrap();
+
+        let mut results = client.query("SELECT VALUE email FROM user").await.unwrap();
+        let emails: Vec<String> = results.take(0).unwrap();
+        assert_eq!(emails, vec!["ada@example.com"]);
+    }
+
+    #[actix_web::test]
+    async fn reports_emails_that_only_differ_in_case() {
+        let client = empty_database().await;
+        client
+            .query("CREATE user SET email = 'ada@example.com'")
+            .query("CREATE user SET email = 'Ada@Example.com'")
+            .await
+            .unwrap()
+            .check()
+            .unwrap();
+
+        let error = MigrationRunner::run(&client).await.err().unwrap();
+        assert!(error.to_string().contains("ada@example.com"));
+
+        let mut results = client
+            .query("SELECT VALUE version FROM migration")
+            .await
+            .unwrap();
+        let applied: Vec<u32> = results.take(0).unwrap();
+        assert!(!applied.contains(&2));
+    }
+
+    #[actix_web::test]
+    async fn keeps_one_token_per_device() {
+        let client = empty_database().await;
+        client
+            .query(
+                "CREATE token SET access_token = 'a', user_id = 'user:1', device_id = 'device:1'",
+            )
+            .query(
+                "CREATE token SET access_token = 'b', user_id = 'user:1', device_id = 'device:1'",
+            )
+            .await
+            .unwrap()
+            .check()
+            .unwrap();
+
+        MigrationRunner::run(&client).await.unwrap();
+
+        let mut results = client.query("SELECT VALUE id FROM token").await.unwrap();
+        let ids: Vec<Thing> = results.take(0).unwrap();
+        assert_eq!(ids.len(), 1);
+    }
+}
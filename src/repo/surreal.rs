@@ -1,32 +1,51 @@
-use std::env;
-
-use surrealdb::engine::remote::ws::{Client, Wss};
+use surrealdb::engine::any::{self, Any};
 use surrealdb::opt::auth::Root;
 use surrealdb::{Result, Surreal};
 
+use crate::config::AppConfig;
+
+use super::migration::MigrationRunner;
+
+// Embedded engines run in process and have no users to sign in as
+pub fn is_embedded(url: &str) -> bool {
+    url.starts_with("mem://")
+}
+
+// The engine comes from the url scheme, wss:// for the remote database and mem:// for an
+// in-memory one, so the controllers run unchanged against both
 #[derive(Debug, Clone)]
 pub struct SurrealDB {
-    pub surreal: Surreal<Client>,
+    pub surreal: Surreal<Any>,
 }
 
 impl SurrealDB {
-    pub async fn init() -> Result<Self> {
-        let client = Surreal::new::<Wss>("mirai-db.fly.dev").await?;
-
-        let password =
-            env::var("SURREAL_PASS").unwrap_or_else(|_| String::from("noaccessforuhoneynonono"));
-
-        client
-            .signin(Root {
-                username: "miraiser",
-                password: password.as_str(),
-            })
-            .await?;
+    pub async fn init(config: &AppConfig) -> Result<Self> {
+        let client = any::connect(config.database.url.as_str()).await?;
+
+        if !is_embedded(&config.database.url) {
+            client
+                .signin(Root {
+                    username: &config.database.username,
+                    password: &config.database.password,
+                })
+                .await?;
+        }
+
+        Self::prepare(client, &config.database.namespace, config.database_name()).await
+    }
 
-        let db_env = env::var("PROJECT_ENV").unwrap_or_else(|_| String::from("development"));
-        let db_name = if db_env == "prod" { "prod" } else { "staging" };
+    // Empty database that lives as long as the client
+    #[cfg(test)]
+    pub async fn memory() -> Result<Self> {
+        let client = any::connect("mem://").await?;
+        Self::prepare(client, "echo", "test").await
+    }
 
-        client.use_ns("echo").use_db(db_name).await?;
+    async fn prepare(client: Surreal<Any>, namespace: &str, database: &str) -> Result<Self> {
+        client.use_ns(namespace).use_db(database).await?;
+        for migration in MigrationRunner::run(&client).await? {
+            log::info!("Applied migration {} {}", migration.version, migration.name);
+        }
 
         Ok(SurrealDB { surreal: client })
     }